@@ -0,0 +1,287 @@
+use crate::brute::{self, Tally};
+use crate::engine::Engine;
+use crate::progress;
+use crate::wildcard::to_fqdn;
+use futures::prelude::*;
+use futures::stream;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{prelude::*, BufReader};
+use std::sync::Arc;
+use trust_dns_proto::rr::Name;
+use trust_dns_resolver::lookup::Lookup;
+
+/// Words used for alterations when no words file is given.
+const DEFAULT_WORDS: [&str; 24] = [
+    "dev",
+    "development",
+    "stg",
+    "stage",
+    "staging",
+    "test",
+    "qa",
+    "uat",
+    "prod",
+    "production",
+    "api",
+    "admin",
+    "internal",
+    "int",
+    "corp",
+    "beta",
+    "demo",
+    "old",
+    "new",
+    "backup",
+    "v1",
+    "v2",
+    "web",
+    "app",
+];
+
+/// The joiners used to attach a word to a label.
+const JOINERS: [&str; 2] = ["-", "."];
+
+/// Resolve alterations of the names found under `domain`.
+///
+/// The candidates are made from the `known` names and go through the
+/// same rate controlled resolution as a brute force run, names that are
+/// already known are not queried again.
+pub fn alter_domain(
+    domain: &str,
+    known: &HashSet<Name>,
+    words: &[String],
+    engine: Engine,
+    concurrency: usize,
+    tally: Arc<Tally>,
+) -> impl Stream<Item = Lookup> {
+    let domain = match domain.parse::<Name>() {
+        Ok(name) => to_fqdn(&name),
+        Err(e) => {
+            progress::note(format!("Invalid domain {}: {}", domain, e));
+            return stream::empty().left_stream();
+        }
+    };
+    let candidates = alterations(known, &domain, words);
+    progress::note(format!(
+        "Resolving {} alterations of {} names",
+        candidates.len(),
+        known.len()
+    ));
+    brute::resolve_names(candidates, domain, engine, concurrency, tally).right_stream()
+}
+
+/// Read the alteration words from a file, one word per line.
+pub fn read_words(words_file: &str) -> Vec<String> {
+    let file = File::open(words_file).expect("Could not open file");
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .map(|word| word.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+pub fn default_words() -> Vec<String> {
+    DEFAULT_WORDS.iter().map(|word| word.to_string()).collect()
+}
+
+/// Generate the alterations of the `known` names under `domain`,
+/// excluding the known names themselves.
+pub fn alterations(known: &HashSet<Name>, domain: &Name, words: &[String]) -> Vec<Name> {
+    let mut seen = HashSet::new();
+    let mut candidates = vec![];
+    let mut sorted = known.iter().collect::<Vec<_>>();
+    sorted.sort();
+    for name in sorted {
+        let labels = match subdomain_labels(name, domain) {
+            Some(labels) => labels,
+            None => continue,
+        };
+        for altered in alter_labels(&labels, words) {
+            let candidate = match Name::from_labels(altered) {
+                Ok(candidate) => to_fqdn(&candidate.append_domain(domain)),
+                Err(_) => continue,
+            };
+            if !known.contains(&candidate) && seen.insert(candidate.clone()) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+/// The labels of `name` below `domain`, or `None` if `name` is not a
+/// subdomain of it.
+fn subdomain_labels(name: &Name, domain: &Name) -> Option<Vec<String>> {
+    if name.is_wildcard() || !domain.zone_of(name) || name.num_labels() <= domain.num_labels() {
+        return None;
+    }
+    let count = (name.num_labels() - domain.num_labels()) as usize;
+    Some(
+        name.iter()
+            .take(count)
+            .map(|label| String::from_utf8_lossy(label).to_lowercase())
+            .collect(),
+    )
+}
+
+/// Apply every alteration to each label in turn.
+fn alter_labels(labels: &[String], words: &[String]) -> Vec<Vec<String>> {
+    let mut altered = vec![];
+    for (i, label) in labels.iter().enumerate() {
+        let replace = |new_labels: Vec<String>| {
+            let mut result = labels[..i].to_vec();
+            result.extend(new_labels);
+            result.extend_from_slice(&labels[i + 1..]);
+            result
+        };
+        for word in words {
+            for joiner in JOINERS.iter() {
+                let before = format!("{}{}{}", word, joiner, label);
+                let after = format!("{}{}{}", label, joiner, word);
+                altered.push(replace(before.split('.').map(String::from).collect()));
+                altered.push(replace(after.split('.').map(String::from).collect()));
+            }
+        }
+        for swapped in swap_words(label, words) {
+            altered.push(replace(vec![swapped]));
+        }
+        for stepped in step_numbers(label) {
+            altered.push(replace(vec![stepped]));
+        }
+    }
+    altered
+}
+
+/// Replace each `-` separated part of a label with every word,
+/// `api-staging` becomes `api-dev`, `dev-staging` and so on.
+fn swap_words(label: &str, words: &[String]) -> Vec<String> {
+    let parts = label.split('-').collect::<Vec<_>>();
+    if parts.len() < 2 {
+        return vec![];
+    }
+    let mut swapped = vec![];
+    for i in 0..parts.len() {
+        for word in words {
+            if parts[i] == word {
+                continue;
+            }
+            let mut new_parts = parts.clone();
+            new_parts[i] = word;
+            swapped.push(new_parts.join("-"));
+        }
+    }
+    swapped
+}
+
+/// Increment and decrement every run of digits in a label, keeping its
+/// zero padding, `web01` becomes `web00` and `web02`.
+fn step_numbers(label: &str) -> Vec<String> {
+    let mut stepped = vec![];
+    let bytes = label.as_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        if !bytes[start].is_ascii_digit() {
+            start += 1;
+            continue;
+        }
+        let end = (start..bytes.len())
+            .find(|i| !bytes[*i].is_ascii_digit())
+            .unwrap_or(bytes.len());
+        let digits = &label[start..end];
+        if let Ok(number) = digits.parse::<u64>() {
+            let width = digits.len();
+            let mut numbers = vec![number.saturating_add(1)];
+            if number > 0 {
+                numbers.push(number - 1);
+            }
+            for n in numbers {
+                stepped.push(format!(
+                    "{}{:0width$}{}",
+                    &label[..start],
+                    n,
+                    &label[end..],
+                    width = width
+                ));
+            }
+        }
+        start = end;
+    }
+    stepped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn names(candidates: &[Name]) -> Vec<String> {
+        candidates.iter().map(|name| name.to_ascii()).collect()
+    }
+
+    #[test]
+    fn test_step_numbers() {
+        assert_eq!(step_numbers("web01"), vec!["web02", "web00"]);
+        assert_eq!(step_numbers("web0"), vec!["web1"]);
+        assert_eq!(
+            step_numbers("a9-b10"),
+            vec!["a10-b10", "a8-b10", "a9-b11", "a9-b09"]
+        );
+        assert!(step_numbers("web").is_empty());
+    }
+
+    #[test]
+    fn test_swap_words() {
+        assert_eq!(
+            swap_words("api-staging", &words(&["dev", "staging"])),
+            vec!["dev-staging", "staging-staging", "api-dev"]
+        );
+        assert!(swap_words("api", &words(&["dev"])).is_empty());
+    }
+
+    #[test]
+    fn test_subdomain_labels() {
+        let domain = Name::from_str("example.com.").unwrap();
+        let name = Name::from_str("a.b.example.com.").unwrap();
+        assert_eq!(subdomain_labels(&name, &domain), Some(words(&["a", "b"])));
+        assert_eq!(subdomain_labels(&domain, &domain), None);
+        let other = Name::from_str("a.example.net.").unwrap();
+        assert_eq!(subdomain_labels(&other, &domain), None);
+    }
+
+    #[test]
+    fn test_alterations() {
+        let domain = Name::from_str("example.com.").unwrap();
+        let known = vec![Name::from_str("api.example.com.").unwrap()]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let candidates = names(&alterations(&known, &domain, &words(&["dev"])));
+        assert_eq!(
+            candidates,
+            vec![
+                "dev-api.example.com.",
+                "api-dev.example.com.",
+                "dev.api.example.com.",
+                "api.dev.example.com.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alterations_skip_known_names() {
+        let domain = Name::from_str("example.com.").unwrap();
+        let known = vec![
+            Name::from_str("web01.example.com.").unwrap(),
+            Name::from_str("web02.example.com.").unwrap(),
+        ]
+        .into_iter()
+        .collect::<HashSet<_>>();
+        let candidates = names(&alterations(&known, &domain, &[]));
+        assert_eq!(candidates, vec!["web00.example.com.", "web03.example.com."]);
+    }
+}
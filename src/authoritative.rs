@@ -0,0 +1,354 @@
+use crate::engine::{Engine, LookupError};
+use crate::progress;
+use crate::rate::RateController;
+use crate::transport::{request, Transport};
+use crate::wildcard::to_fqdn;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::{Message, Query};
+use trust_dns_proto::rr::{Name, RData, RecordType};
+
+/// The most referrals followed for a single query.
+const MAX_REFERRALS: usize = 5;
+
+/// The IPv4 addresses of the name servers of `zone`, looked up through
+/// the recursive `engine`.
+pub async fn zone_name_servers(
+    zone: &Name,
+    engine: &Engine,
+    port: u16,
+) -> Result<Vec<SocketAddr>, String> {
+    let hosts = ns_hosts(zone, engine).await?;
+    let addrs = host_addrs(&hosts, engine, port).await;
+    if addrs.is_empty() {
+        return Err(format!(
+            "Could not find the addresses of the name servers of {}",
+            zone
+        ));
+    }
+    progress::note(format!(
+        "Querying the authoritative name servers of {}: {}",
+        zone.to_ascii(),
+        addrs
+            .iter()
+            .map(|addr| addr.ip().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    Ok(addrs)
+}
+
+/// The host names of the name servers of `zone`.
+pub async fn ns_hosts(zone: &Name, engine: &Engine) -> Result<Vec<Name>, String> {
+    let hosts = engine
+        .lookup(zone.clone(), RecordType::NS)
+        .await
+        .map_err(|e| format!("Could not find the name servers of {}: {}", zone, e))?
+        .record_iter()
+        .filter_map(|record| match record.rdata() {
+            RData::NS(host) => Some(to_fqdn(host)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if hosts.is_empty() {
+        return Err(format!("No name servers found for {}", zone));
+    }
+    Ok(hosts)
+}
+
+async fn host_addrs(hosts: &[Name], engine: &Engine, port: u16) -> Vec<SocketAddr> {
+    let mut addrs = vec![];
+    for host in hosts {
+        if let Ok(lookup) = engine.lookup(host.clone(), RecordType::A).await {
+            for record in lookup.record_iter() {
+                if let RData::A(ip) = record.rdata() {
+                    let addr = SocketAddr::new(IpAddr::V4(*ip), port);
+                    if !addrs.contains(&addr) {
+                        addrs.push(addr);
+                    }
+                }
+            }
+        }
+    }
+    addrs
+}
+
+/// The subzones the authoritative name servers delegated, and where
+/// their own name servers are.
+///
+/// A name under a known subzone is sent straight to the subzone's name
+/// servers, so each delegation is only followed once.
+pub struct Delegations {
+    zones: Mutex<HashMap<Name, Vec<SocketAddr>>>,
+    /// Looks up the name servers referred to without glue, recursively.
+    recursive: Engine,
+    port: u16,
+}
+
+impl Delegations {
+    pub fn new(recursive: Engine, port: u16) -> Delegations {
+        Delegations {
+            zones: Mutex::new(HashMap::new()),
+            recursive,
+            port,
+        }
+    }
+
+    /// Send `query` without recursion, to the name servers of the
+    /// closest subzone known or to `server`, following referrals until a
+    /// server answers for the name.  The time the round trips took is
+    /// added to `latency`.
+    pub async fn query(
+        &self,
+        transport: &Transport,
+        rate: &RateController,
+        query: &Query,
+        server: SocketAddr,
+        latency: &mut Duration,
+    ) -> Result<Message, LookupError> {
+        let mut server = self.closest(query.name()).unwrap_or(server);
+        let mut visited = HashSet::new();
+        for _ in 0..=MAX_REFERRALS {
+            rate.wait().await;
+            let sent = Instant::now();
+            let response = transport
+                .query(&server.into(), request(query.clone(), false), None)
+                .await;
+            *latency += sent.elapsed();
+            let response = response?;
+            let zone = match referral(&response, query.name()) {
+                // A lame server points back at the root.
+                Some(zone) if zone.is_root() => {
+                    return Err(LookupError::Failed(format!(
+                        "upward referral from {}",
+                        server
+                    )))
+                }
+                Some(zone) if visited.insert(zone.clone()) => zone,
+                Some(zone) => {
+                    return Err(LookupError::Failed(format!(
+                        "referral loop at {}",
+                        zone.to_ascii()
+                    )))
+                }
+                None => return Ok(response),
+            };
+            let addrs = self.delegate(&zone, &response).await;
+            server = *addrs.choose(&mut thread_rng()).ok_or_else(|| {
+                LookupError::Failed(format!(
+                    "no address for the name servers of {}",
+                    zone.to_ascii()
+                ))
+            })?;
+        }
+        Err(LookupError::Failed(format!(
+            "more than {} referrals",
+            MAX_REFERRALS
+        )))
+    }
+
+    /// A server of the deepest known subzone holding `name`.
+    fn closest(&self, name: &Name) -> Option<SocketAddr> {
+        let zones = self.zones.lock().expect("delegations lock poisoned");
+        zones
+            .iter()
+            .filter(|(zone, addrs)| zone.zone_of(name) && !addrs.is_empty())
+            .max_by_key(|(zone, _)| zone.num_labels())
+            .and_then(|(_, addrs)| addrs.choose(&mut thread_rng()).copied())
+    }
+
+    /// Remember the name servers of the subzone a referral points to,
+    /// taken from its glue or looked up when there is none.
+    async fn delegate(&self, zone: &Name, referral: &Message) -> Vec<SocketAddr> {
+        if let Some(addrs) = self
+            .zones
+            .lock()
+            .expect("delegations lock poisoned")
+            .get(zone)
+        {
+            return addrs.clone();
+        }
+        let hosts = referral
+            .name_servers()
+            .iter()
+            .filter_map(|record| match record.rdata() {
+                RData::NS(host) => Some(to_fqdn(host)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut addrs = referral
+            .additionals()
+            .iter()
+            .filter(|record| hosts.contains(&to_fqdn(record.name())))
+            .filter_map(|record| match record.rdata() {
+                RData::A(ip) => Some(SocketAddr::new(IpAddr::V4(*ip), self.port)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if addrs.is_empty() {
+            // Boxed, the recursive engine may itself follow delegations.
+            addrs = Box::pin(host_addrs(&hosts, &self.recursive, self.port)).await;
+        }
+        self.zones
+            .lock()
+            .expect("delegations lock poisoned")
+            .insert(zone.clone(), addrs.clone());
+        addrs
+    }
+}
+
+/// The subzone a response delegates `name` to, when it is a referral
+/// rather than an answer.
+fn referral(response: &Message, name: &Name) -> Option<Name> {
+    if response.authoritative() || !response.answers().is_empty() {
+        return None;
+    }
+    response
+        .name_servers()
+        .iter()
+        .find_map(|record| match record.rdata() {
+            RData::NS(_) if record.name().zone_of(name) => Some(to_fqdn(record.name())),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::transport;
+    use crate::pool::Limits;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+    use trust_dns_proto::op::{MessageType, OpCode, ResponseCode};
+    use trust_dns_proto::rr::Record;
+    use trust_dns_resolver::config::NameServerConfigGroup;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    /// Serve example.com on 127.0.0.1 and its subzone sub.example.com on
+    /// 127.0.0.2, on the same port.  Both refuse recursive queries, www
+    /// resolves in each zone and every other name does not exist.
+    async fn stand_in_zones() -> SocketAddr {
+        let parent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = parent.local_addr().unwrap();
+        let child = UdpSocket::bind(("127.0.0.2", addr.port())).await.unwrap();
+        tokio::spawn(serve(parent, |query, response| {
+            let sub = name("sub.example.com.");
+            if sub.zone_of(query.name()) {
+                response.add_name_server(Record::from_rdata(
+                    sub,
+                    60,
+                    RData::NS(name("ns.sub.example.com.")),
+                ));
+                response.add_additional(Record::from_rdata(
+                    name("ns.sub.example.com."),
+                    60,
+                    RData::A("127.0.0.2".parse().unwrap()),
+                ));
+                return;
+            }
+            respond(query, response, "www.example.com.", "10.0.0.1")
+        }));
+        tokio::spawn(serve(child, |query, response| {
+            respond(query, response, "www.sub.example.com.", "10.0.0.2")
+        }));
+        addr
+    }
+
+    fn respond(query: &Query, response: &mut Message, www: &str, ip: &str) {
+        response.set_authoritative(true);
+        if query.name() != &name(www) {
+            response.set_response_code(ResponseCode::NXDomain);
+        } else if query.query_type() == RecordType::A {
+            response.add_answer(Record::from_rdata(
+                query.name().clone(),
+                60,
+                RData::A(ip.parse().unwrap()),
+            ));
+        }
+    }
+
+    async fn serve(mut socket: UdpSocket, answer: fn(&Query, &mut Message)) {
+        let mut buf = vec![0; 4096];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let request = Message::from_vec(&buf[..len]).unwrap();
+            let query = request.queries()[0].clone();
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_op_code(OpCode::Query)
+                .add_query(query.clone());
+            if request.recursion_desired() {
+                response.set_response_code(ResponseCode::Refused);
+            } else {
+                answer(&query, &mut response);
+            }
+            let bytes = response.to_vec().unwrap();
+            socket.send_to(&bytes, &from).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_referral() {
+        let mut response = Message::new();
+        response.add_name_server(Record::from_rdata(
+            name("sub.example.com."),
+            60,
+            RData::NS(name("ns.sub.example.com.")),
+        ));
+        assert_eq!(
+            referral(&response, &name("www.sub.example.com.")),
+            Some(name("sub.example.com."))
+        );
+        assert_eq!(referral(&response, &name("www.example.com.")), None);
+        response.set_authoritative(true);
+        assert_eq!(referral(&response, &name("www.sub.example.com.")), None);
+    }
+
+    #[tokio::test]
+    async fn test_authoritative_engine_follows_referrals() {
+        let server = stand_in_zones().await;
+        let rate = Arc::new(RateController::new(1_000_000, 1_000_000));
+        let recursive = Engine::recursive(
+            &NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port()),
+            transport(),
+            Limits::default(),
+            rate.clone(),
+        );
+        let engine = Engine::authoritative(
+            vec![server],
+            transport(),
+            Delegations::new(recursive, server.port()),
+            Limits::default(),
+            rate,
+        )
+        .await
+        .unwrap();
+        let lookup = engine.lookup_ip(name("www.example.com.")).await.unwrap();
+        assert_eq!(lookup.record_iter().count(), 1);
+        for _ in 0..2 {
+            let lookup = engine
+                .lookup_ip(name("www.sub.example.com."))
+                .await
+                .unwrap();
+            let ips = lookup
+                .record_iter()
+                .map(|record| record.rdata().clone())
+                .collect::<Vec<_>>();
+            assert_eq!(ips, vec![RData::A("10.0.0.2".parse().unwrap())]);
+        }
+        assert_eq!(
+            engine.lookup_ip(name("nx.sub.example.com.")).await.err(),
+            Some(LookupError::NxDomain)
+        );
+    }
+}
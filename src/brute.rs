@@ -1,61 +1,384 @@
+use crate::engine::{Engine, LookupError};
+use crate::progress;
+use crate::wildcard::{to_fqdn, WildcardCache};
+use crate::wordlist::{fingerprint, Wordlist};
+use futures::channel::mpsc;
 use futures::prelude::*;
 use futures::stream;
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-use std::fs::File;
-use std::io::{prelude::*, BufReader};
-use stream_throttle::{ThrottlePool, ThrottledStream};
-use trust_dns_proto::rr::Record;
-use trust_dns_resolver::TokioAsyncResolver;
-
-pub async fn brute_force_domain(
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use trust_dns_proto::rr::Name;
+use trust_dns_resolver::lookup::Lookup;
+
+/// Brute force the subdomains of `domain` from the wordlist.
+///
+/// Every name discovered is used as a new base for the wordlist, until
+/// `depth` levels below the domain have been enumerated.  A name is
+/// queried once however many bases lead to it, as far as the fixed-size
+/// table of the names tried remembers it.
+///
+/// Lookups are streamed as soon as they resolve.  The enumeration runs on
+/// its own task and hands its hits over a channel bounded by
+/// `concurrency`, so a slow consumer holds resolution back instead of
+/// results piling up in memory.  What became of every other candidate is
+/// counted in the `tally`.
+pub fn brute_force_domain(
     domain: &str,
-    subdomains_file: &str,
-    throttle_pool: ThrottlePool,
-    resolver: &TokioAsyncResolver,
+    wordlist: Wordlist,
+    engine: Engine,
     concurrency: usize,
-) -> Vec<Record> {
-    if let Ok(result) = is_wildcard_domain(domain, resolver).await {
-        println!("Wild card resolution is enabled on this domain");
-        println!("All domains will resolve to these ips");
-        return result
-            .as_lookup()
-            .record_iter()
-            .cloned()
-            .collect::<Vec<_>>();
-    }
-
-    let file = File::open(subdomains_file).expect("Could not open file");
-    let reader = BufReader::new(file);
-
-    let stream = stream::iter(reader.lines())
-        .throttle(throttle_pool)
-        .map_err(|e| format!("error {}", e))
-        .and_then(|prefix| {
-            future::ok(
-                resolver
-                    .lookup_ip(format!("{}.{}", prefix, domain))
-                    .map_err(|e| format!("error: {}", e)),
-            )
-        })
-        .try_buffer_unordered(concurrency)
-        .filter(|x| future::ready(x.is_ok()))
-        .map(|x| x.unwrap())
-        .map(|x| x.as_lookup().clone());
-    stream
-        .collect::<Vec<_>>()
+    depth: usize,
+    tally: Arc<Tally>,
+) -> impl Stream<Item = Lookup> {
+    let (sender, receiver) = mpsc::channel(concurrency);
+    let domain = domain.to_string();
+    tokio::spawn(async move {
+        brute_force(
+            &domain,
+            &wordlist,
+            &engine,
+            concurrency,
+            depth,
+            &tally,
+            sender,
+        )
         .await
-        .iter()
-        .flat_map(|x| x.record_iter().cloned())
-        .collect::<Vec<_>>()
+    });
+    receiver
 }
 
-/// Check if domain is configured with Wildcard resolution
-/// by requesting a random address
-async fn is_wildcard_domain(
+async fn brute_force(
     domain: &str,
-    resolver: &TokioAsyncResolver,
-) -> Result<trust_dns_resolver::lookup_ip::LookupIp, trust_dns_resolver::error::ResolveError> {
-    let prefix: String = thread_rng().sample_iter(&Alphanumeric).take(12).collect();
-    resolver.lookup_ip(format!("{}.{}", prefix, domain)).await
+    wordlist: &Wordlist,
+    engine: &Engine,
+    concurrency: usize,
+    depth: usize,
+    tally: &Tally,
+    mut sender: mpsc::Sender<Lookup>,
+) {
+    let domain = match Name::from_str(domain) {
+        Ok(name) => to_fqdn(&name),
+        Err(e) => {
+            progress::note(format!("Invalid domain {}: {}", domain, e));
+            return;
+        }
+    };
+    if let Some(keyspace) = wordlist.keyspace() {
+        progress::note(format!(
+            "Pattern keyspace: {} candidates per base",
+            keyspace
+        ));
+    }
+    let per_base = wordlist.size_hint();
+    let wildcards = WildcardCache::new();
+    // A flat run sees each word once as the wordlist is deduplicated, so
+    // the names tried are only remembered when recursing, by fingerprint
+    // to keep the set compact.
+    let mut tried = HashSet::new();
+    let mut bases = vec![domain];
+    for level in 1..=depth {
+        let mut discovered = vec![];
+        for base in bases {
+            progress::note(format!(
+                "Enumerating subdomains of {} at {:.0} queries/s",
+                base.to_ascii(),
+                engine.rate()
+            ));
+            wildcards.probe(&base, engine).await;
+            tally.queue(per_base);
+            let candidates = candidate_names(wordlist, &base).filter(|name| {
+                let untried = depth == 1 || tried.insert(fingerprint(name));
+                if !untried {
+                    tally.unqueue(1);
+                }
+                untried
+            });
+            let hits = resolve_candidates(candidates, engine, concurrency, &wildcards, tally);
+            futures::pin_mut!(hits);
+            while let Some(lookup) = hits.next().await {
+                if level < depth {
+                    discovered.push(to_fqdn(lookup.query().name()));
+                }
+                if sender.send(lookup).await.is_err() {
+                    return;
+                }
+            }
+        }
+        bases = discovered;
+    }
+}
+
+/// The words of the wordlist as names under `base`, skipping words that
+/// do not form a valid name.
+fn candidate_names<'a>(
+    wordlist: &'a Wordlist,
+    base: &'a Name,
+) -> impl Iterator<Item = Name> + Send + 'a {
+    wordlist.words().filter_map(move |prefix| {
+        Name::from_str(&prefix)
+            .map(|prefix| to_fqdn(&prefix.append_domain(base)))
+            .ok()
+    })
+}
+
+/// Resolve a fixed list of names under `parent` on a task of its own,
+/// streaming the lookups like [`brute_force_domain`].
+pub fn resolve_names(
+    candidates: Vec<Name>,
+    parent: Name,
+    engine: Engine,
+    concurrency: usize,
+    tally: Arc<Tally>,
+) -> impl Stream<Item = Lookup> {
+    let (mut sender, receiver) = mpsc::channel(concurrency);
+    tokio::spawn(async move {
+        let wildcards = WildcardCache::new();
+        wildcards.probe(&parent, &engine).await;
+        tally.queue(Some(candidates.len() as u128));
+        let hits = resolve_candidates(
+            candidates.into_iter(),
+            &engine,
+            concurrency,
+            &wildcards,
+            &tally,
+        );
+        futures::pin_mut!(hits);
+        while let Some(lookup) = hits.next().await {
+            if sender.send(lookup).await.is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// Resolve the candidate names through the engine, counting the misses
+/// and the answers that match a wildcard in the `tally`.
+fn resolve_candidates<'a>(
+    candidates: impl Iterator<Item = Name> + Send + 'a,
+    engine: &'a Engine,
+    concurrency: usize,
+    wildcards: &'a WildcardCache,
+    tally: &'a Tally,
+) -> impl Stream<Item = Lookup> + Send + 'a {
+    stream::iter(candidates)
+        .map(move |name| engine.lookup_ip(name.clone()).map(|result| (name, result)))
+        .buffer_unordered(concurrency)
+        .filter_map(move |(name, result)| async move {
+            let lookup = match result {
+                Ok(lookup) => lookup,
+                Err(e) => {
+                    tally.missed(name, e);
+                    return None;
+                }
+            };
+            if wildcards
+                .is_wildcard(lookup.query().name(), lookup.record_iter(), engine)
+                .await
+            {
+                tally.wildcard();
+                None
+            } else {
+                tally.found();
+                Some(lookup)
+            }
+        })
+}
+
+/// The number of candidates without a definitive answer kept to be listed,
+/// the rest are only counted.
+const LISTED_UNRESOLVED: usize = 1000;
+
+/// What became of the candidates of a run.
+///
+/// Definitive misses are only counted, the first candidates that never got
+/// a definitive answer are kept so the gaps in coverage can be listed.
+#[derive(Default)]
+pub struct Tally {
+    counts: Mutex<Counts>,
+    unresolved: Mutex<Vec<(Name, LookupError)>>,
+}
+
+/// The number of candidates queued and of each outcome so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    /// The candidates expected, unknown when a wordlist is read from
+    /// standard input.
+    pub queued: Option<u128>,
+    pub found: usize,
+    pub wildcard: usize,
+    pub nxdomain: usize,
+    pub nodata: usize,
+    pub servfail: usize,
+    pub refused: usize,
+    pub timeout: usize,
+    pub failed: usize,
+}
+
+impl Counts {
+    /// The candidates that have an outcome.
+    pub fn done(&self) -> usize {
+        self.found + self.wildcard + self.nxdomain + self.nodata + self.unresolved()
+    }
+
+    /// The candidates without a definitive answer.
+    pub fn unresolved(&self) -> usize {
+        self.servfail + self.refused + self.timeout + self.failed
+    }
+}
+
+impl Tally {
+    pub fn new() -> Tally {
+        Tally {
+            counts: Mutex::new(Counts {
+                queued: Some(0),
+                ..Counts::default()
+            }),
+            unresolved: Mutex::new(vec![]),
+        }
+    }
+
+    fn lock_counts(&self) -> std::sync::MutexGuard<'_, Counts> {
+        self.counts.lock().expect("tally lock poisoned")
+    }
+
+    /// Expect `candidates` more candidates, `None` when their number is
+    /// not known.
+    pub fn queue(&self, candidates: Option<u128>) {
+        let mut counts = self.lock_counts();
+        counts.queued = match (counts.queued, candidates) {
+            (Some(queued), Some(candidates)) => Some(queued.saturating_add(candidates)),
+            _ => None,
+        };
+    }
+
+    /// Stop expecting `candidates` of those queued, which turned out not
+    /// to need trying.
+    pub fn unqueue(&self, candidates: u128) {
+        let mut counts = self.lock_counts();
+        counts.queued = counts
+            .queued
+            .map(|queued| queued.saturating_sub(candidates));
+    }
+
+    fn found(&self) {
+        self.lock_counts().found += 1;
+    }
+
+    fn wildcard(&self) {
+        self.lock_counts().wildcard += 1;
+    }
+
+    fn missed(&self, name: Name, error: LookupError) {
+        let mut counts = self.lock_counts();
+        match error {
+            LookupError::NxDomain => counts.nxdomain += 1,
+            LookupError::NoData => counts.nodata += 1,
+            error => {
+                match error {
+                    LookupError::ServFail => counts.servfail += 1,
+                    LookupError::Refused => counts.refused += 1,
+                    LookupError::Timeout => counts.timeout += 1,
+                    _ => counts.failed += 1,
+                }
+                let mut unresolved = self.unresolved.lock().expect("tally lock poisoned");
+                if unresolved.len() < LISTED_UNRESOLVED {
+                    unresolved.push((name, error));
+                }
+            }
+        }
+    }
+
+    /// The counts so far.
+    pub fn counts(&self) -> Counts {
+        *self.lock_counts()
+    }
+
+    /// The first candidates without a definitive answer, up to
+    /// [`LISTED_UNRESOLVED`] of them, with the last failure of each.
+    pub fn unresolved(&self) -> Vec<(Name, LookupError)> {
+        let mut unresolved = self.unresolved.lock().expect("tally lock poisoned").clone();
+        unresolved.sort_by(|a, b| a.0.cmp(&b.0));
+        unresolved
+    }
+
+    /// A one line summary of the outcomes.
+    pub fn summary(&self) -> String {
+        let counts = self.counts();
+        let mut summary = format!(
+            "{} candidates: {} found, {} wildcard",
+            counts.done(),
+            counts.found,
+            counts.wildcard
+        );
+        if counts.nxdomain > 0 || counts.nodata > 0 {
+            summary.push_str(&format!(
+                ", {} NXDOMAIN, {} NODATA",
+                counts.nxdomain, counts.nodata
+            ));
+        }
+        summary.push_str(&format!(", {} unresolved", counts.unresolved()));
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    #[test]
+    fn test_tally_summary() {
+        let tally = Tally::new();
+        tally.found();
+        tally.wildcard();
+        tally.missed(name("a.example.com."), LookupError::NxDomain);
+        tally.missed(name("b.example.com."), LookupError::NxDomain);
+        tally.missed(name("c.example.com."), LookupError::NoData);
+        tally.missed(name("d.example.com."), LookupError::Timeout);
+        assert_eq!(
+            tally.summary(),
+            "6 candidates: 1 found, 1 wildcard, 2 NXDOMAIN, 1 NODATA, 1 unresolved"
+        );
+    }
+
+    #[test]
+    fn test_tally_queue() {
+        let tally = Tally::new();
+        tally.queue(Some(10));
+        tally.queue(Some(5));
+        assert_eq!(tally.counts().queued, Some(15));
+        tally.queue(None);
+        tally.queue(Some(5));
+        assert_eq!(tally.counts().queued, None);
+        let tally = Tally::new();
+        tally.queue(Some(10));
+        tally.unqueue(4);
+        assert_eq!(tally.counts().queued, Some(6));
+    }
+
+    #[test]
+    fn test_tally_lists_unresolved() {
+        let tally = Tally::new();
+        tally.missed(name("z.example.com."), LookupError::ServFail);
+        tally.missed(name("a.example.com."), LookupError::NxDomain);
+        tally.missed(name("b.example.com."), LookupError::Refused);
+        assert_eq!(
+            tally.unresolved(),
+            vec![
+                (name("b.example.com."), LookupError::Refused),
+                (name("z.example.com."), LookupError::ServFail),
+            ]
+        );
+        assert_eq!(
+            tally.summary(),
+            "3 candidates: 0 found, 0 wildcard, 1 NXDOMAIN, 0 NODATA, 2 unresolved"
+        );
+        assert_eq!(tally.counts().servfail, 1);
+        assert_eq!(tally.counts().refused, 1);
+    }
 }
@@ -0,0 +1,694 @@
+use crate::zonefile;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use trust_dns_client::rr::{DNSClass, Name, RData, Record, RecordType};
+use trust_dns_client::serialize::txt::{Lexer, Parser, Token};
+use trust_dns_proto::serialize::binary::{BinDecoder, Restrict};
+
+/// A zone as the record sets of each owner name and type.
+///
+/// Records are read into their RDATA types wherever the tool knows them
+/// and written back in presentation format, with names absolute and
+/// lowercase, so zones transferred, saved as master files or exported as
+/// JSON compare alike, whether their types are written with mnemonics or
+/// in the RFC 3597 generic format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub origin: Name,
+    rrsets: BTreeMap<(Name, String), RecordSet>,
+}
+
+/// The TTL and RDATA of the records of one owner name and type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordSet {
+    pub ttl: u32,
+    pub rdata: BTreeSet<String>,
+}
+
+impl Zone {
+    fn new(origin: &Name) -> Zone {
+        Zone {
+            origin: origin.clone(),
+            rrsets: BTreeMap::new(),
+        }
+    }
+
+    /// A zone of the records of a transfer.
+    pub fn from_records(origin: &Name, records: &[Record]) -> Result<Zone, String> {
+        let mut zone = Zone::new(origin);
+        for record in records {
+            let (record_type, rdata) = zonefile::presentation(record)?;
+            zone.insert(record.name(), record_type, record.ttl(), rdata);
+        }
+        Ok(zone)
+    }
+
+    /// Read a zone from a JSON export or, otherwise, a master file.
+    pub fn from_file(origin: &Name, path: &str) -> Result<Zone, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read the zone {}: {}", path, e))?;
+        let zone = if text.trim_start().starts_with(['[', '{']) {
+            Zone::from_json(origin, &text)
+        } else {
+            Zone::from_master_file(origin, &text)
+        };
+        zone.map_err(|e| format!("{} in {}", e, path))
+    }
+
+    /// Parse an RFC 1035 master file, as written by `--zone-file` or
+    /// BIND.
+    pub fn from_master_file(origin: &Name, text: &str) -> Result<Zone, String> {
+        let mut zone = Zone::new(origin);
+        let mut current = origin.clone();
+        let mut default_ttl = None;
+        let mut last_ttl = None;
+        let mut owner = None;
+        let mut lexer = Lexer::new(text);
+        let mut entry = 0;
+        loop {
+            let mut tokens = vec![];
+            let more = loop {
+                match lexer.next_token() {
+                    Ok(Some(Token::EOL)) => break true,
+                    Ok(Some(token)) => tokens.push(token),
+                    Ok(None) => break false,
+                    Err(e) => return Err(format!("{} after entry {}", e, entry)),
+                }
+            };
+            if tokens.iter().any(|token| *token != Token::Blank) {
+                entry += 1;
+            }
+            let at = |e: String| format!("{} in entry {}", e, entry);
+            let mut tokens = tokens.into_iter();
+            match tokens.next() {
+                None => {}
+                Some(Token::Origin) => {
+                    let name = fields(tokens, &current).into_iter().next();
+                    let name = name.ok_or_else(|| at("No origin".to_string()))?;
+                    current = absolute(&name, &current).map_err(at)?;
+                }
+                Some(Token::Ttl) => {
+                    let value = fields(tokens, &current).into_iter().next();
+                    let value = value.ok_or_else(|| at("No TTL".to_string()))?;
+                    default_ttl = Some(ttl(&value).map_err(at)?);
+                }
+                Some(Token::Include) => return Err(at("Unsupported directive $INCLUDE".into())),
+                Some(first) => {
+                    match first {
+                        Token::Blank => {}
+                        Token::At => owner = Some(current.clone()),
+                        Token::CharData(name) => {
+                            owner = Some(absolute(&name, &current).map_err(at)?)
+                        }
+                        token => return Err(at(format!("Unexpected {:?}", token))),
+                    }
+                    let mut fields = fields(tokens, &current).into_iter().peekable();
+                    if fields.peek().is_some() {
+                        let owner = owner
+                            .clone()
+                            .ok_or_else(|| at("No owner name".to_string()))?;
+                        let mut record_ttl = None;
+                        let record_type = loop {
+                            match fields.next() {
+                                Some(field)
+                                    if DNSClass::from_str(&field.to_uppercase()).is_ok() =>
+                                {
+                                    continue
+                                }
+                                Some(field)
+                                    if record_ttl.is_none()
+                                        && field.starts_with(|c: char| c.is_ascii_digit()) =>
+                                {
+                                    record_ttl = Some(ttl(&field).map_err(at)?)
+                                }
+                                Some(field) => break field,
+                                None => return Err(at("No record type".to_string())),
+                            }
+                        };
+                        let record_ttl = record_ttl
+                            .or(default_ttl)
+                            .or(last_ttl)
+                            .ok_or_else(|| at("No TTL".to_string()))?;
+                        last_ttl = Some(record_ttl);
+                        let fields = fields.collect::<Vec<_>>();
+                        let (record_type, rdata) =
+                            normalize(&record_type, &fields, &current).map_err(at)?;
+                        zone.insert(&owner, record_type, record_ttl, rdata);
+                    }
+                }
+            }
+            if !more {
+                return Ok(zone);
+            }
+        }
+    }
+
+    /// Parse a JSON export, an array of records, or an object with one
+    /// under `records`, each with a `name`, `type`, `ttl` and its RDATA
+    /// in presentation format under `data` or `rdata`.
+    pub fn from_json(origin: &Name, text: &str) -> Result<Zone, String> {
+        let value =
+            serde_json::from_str::<Value>(text).map_err(|e| format!("Invalid JSON: {}", e))?;
+        let records = match &value {
+            Value::Object(object) => object.get("records").and_then(Value::as_array),
+            records => records.as_array(),
+        }
+        .ok_or_else(|| "No array of records".to_string())?;
+        let mut zone = Zone::new(origin);
+        for (i, record) in records.iter().enumerate() {
+            let field = |key: &str| record.get(key).and_then(Value::as_str);
+            let missing = |what: &str| format!("Record {} has no {}", i + 1, what);
+            let owner = field("name").ok_or_else(|| missing("name"))?;
+            let record_type = field("type").ok_or_else(|| missing("type"))?;
+            let record_ttl = record
+                .get("ttl")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| missing("ttl"))? as u32;
+            let rdata = field("data")
+                .or_else(|| field("rdata"))
+                .ok_or_else(|| missing("data"))?;
+            // The text of a TXT record is often exported unquoted, as one
+            // string.
+            let fields = if ["TXT", "SPF"].contains(&record_type.to_uppercase().as_str())
+                && !rdata.starts_with('"')
+            {
+                vec![rdata.to_string()]
+            } else {
+                let mut lexer = Lexer::new(rdata);
+                let mut tokens = vec![];
+                while let Some(token) = lexer
+                    .next_token()
+                    .map_err(|e| format!("{} in record {}", e, i + 1))?
+                {
+                    tokens.push(token);
+                }
+                fields(tokens, origin)
+            };
+            let owner = absolute(owner, origin)?;
+            let (record_type, rdata) = normalize(record_type, &fields, origin)
+                .map_err(|e| format!("{} in record {}", e, i + 1))?;
+            zone.insert(&owner, record_type, record_ttl, rdata);
+        }
+        Ok(zone)
+    }
+
+    fn insert(&mut self, owner: &Name, record_type: String, ttl: u32, rdata: String) {
+        let rrset = self
+            .rrsets
+            .entry((owner.to_lowercase(), record_type))
+            .or_insert(RecordSet {
+                ttl,
+                rdata: BTreeSet::new(),
+            });
+        // The records of a set share a TTL, the lowest is the one that
+        // counts when they do not.
+        rrset.ttl = rrset.ttl.min(ttl);
+        rrset.rdata.insert(rdata);
+    }
+
+    /// The serial of the SOA record of the zone.
+    pub fn serial(&self) -> Option<u32> {
+        self.rrsets
+            .get(&(self.origin.clone(), "SOA".to_string()))?
+            .rdata
+            .iter()
+            .next()?
+            .split_whitespace()
+            .nth(2)?
+            .parse()
+            .ok()
+    }
+}
+
+/// The text of the tokens of an entry, with `@` standing for `origin`.
+fn fields(tokens: impl IntoIterator<Item = Token>, origin: &Name) -> Vec<String> {
+    let mut fields = vec![];
+    for token in tokens {
+        match token {
+            Token::CharData(field) => fields.push(field),
+            Token::List(list) => fields.extend(list),
+            Token::At => fields.push(zonefile::name(origin)),
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// A name made absolute with `origin` when relative.
+fn absolute(name: &str, origin: &Name) -> Result<Name, String> {
+    if name == "@" {
+        return Ok(origin.clone());
+    }
+    Name::parse(name, Some(origin)).map_err(|e| format!("Invalid name {}: {}", name, e.kind()))
+}
+
+/// A TTL in seconds, or with the units BIND allows, e.g. `1h30m`.
+fn ttl(field: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid TTL {}", field);
+    if field.bytes().all(|b| b.is_ascii_digit()) {
+        return field.parse().map_err(|_| invalid());
+    }
+    // The parser does not check for overflows, numbers of up to five
+    // digits before each unit keep the total well within range.
+    let fits = field
+        .split(|c: char| c.is_ascii_alphabetic())
+        .all(|number| number.len() <= 5);
+    if !fits || field.len() > 20 {
+        return Err(invalid());
+    }
+    Parser::parse_time(field).map_err(|_| invalid())
+}
+
+/// The type and RDATA of a record given in presentation format, in the
+/// form of transferred records.
+///
+/// The types the parser knows, and those in the generic format, are read
+/// into their RDATA and written back.  The DNSSEC types it can not read
+/// and the types unknown to it are kept as they are, with names absolute
+/// and lowercase and the base64 or hex that may be split over several
+/// fields joined.
+fn normalize(
+    record_type: &str,
+    fields: &[String],
+    origin: &Name,
+) -> Result<(String, String), String> {
+    let mnemonic = record_type.to_uppercase();
+    let parsed = match mnemonic.strip_prefix("TYPE") {
+        Some(code) if code.parse::<u16>().is_ok() => {
+            Some(RecordType::from(code.parse::<u16>().expect("code checked")))
+        }
+        _ => RecordType::from_str(&mnemonic).ok(),
+    };
+    let rdata = match parsed {
+        Some(record_type) if fields.first().map(String::as_str) == Some("\\#") => {
+            Some(generic(record_type, fields)?)
+        }
+        Some(RecordType::DNSSEC(_)) | Some(RecordType::Unknown(_)) | None => None,
+        Some(record_type) => Some(parse(record_type, fields, origin)?),
+    };
+    match (parsed, rdata) {
+        (Some(record_type), Some(rdata)) => {
+            let mut record = Record::new();
+            record.set_rr_type(record_type).set_rdata(rdata);
+            zonefile::presentation(&record)
+        }
+        (Some(record_type), None) => Ok((
+            zonefile::type_name(record_type),
+            text(&mnemonic, fields, origin)?,
+        )),
+        (None, _) => Ok((mnemonic.clone(), text(&mnemonic, fields, origin)?)),
+    }
+}
+
+/// RDATA in the RFC 3597 `\# length hex` format.
+fn generic(record_type: RecordType, fields: &[String]) -> Result<RData, String> {
+    let length = fields
+        .get(1)
+        .and_then(|length| length.parse::<u16>().ok())
+        .ok_or_else(|| "Invalid generic RDATA length".to_string())?;
+    let hex = fields.get(2..).unwrap_or(&[]).concat();
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .filter(|bytes| bytes.len() == usize::from(length))
+        .ok_or_else(|| format!("Invalid generic RDATA {}", hex))?;
+    let mut decoder = BinDecoder::new(&bytes);
+    RData::read(&mut decoder, record_type, Restrict::new(length)).map_err(|e| {
+        format!(
+            "Invalid {} RDATA: {}",
+            zonefile::type_name(record_type),
+            e.kind()
+        )
+    })
+}
+
+/// RDATA read with the master file parser, from a zone of that record
+/// alone.
+fn parse(record_type: RecordType, fields: &[String], origin: &Name) -> Result<RData, String> {
+    let quoted = fields
+        .iter()
+        .map(|field| format!("\"{}\"", field.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let text = format!("@ 0 IN {} {}\n", record_type, quoted);
+    let invalid = |e: String| format!("Invalid {} RDATA: {}", record_type, e);
+    let (_, rrsets) = Parser::new()
+        .parse(Lexer::new(&text), Some(origin.clone()))
+        .map_err(|e| invalid(e.to_string()))?;
+    rrsets
+        .into_values()
+        .flat_map(|rrset| rrset.records_without_rrsigs().cloned().collect::<Vec<_>>())
+        .map(|record| record.rdata().clone())
+        .next()
+        .ok_or_else(|| invalid("no record".to_string()))
+}
+
+/// RDATA fields joined in the form the DNSSEC types are written in by
+/// the tool, others kept as they are.
+fn text(record_type: &str, fields: &[String], origin: &Name) -> Result<String, String> {
+    // The fields holding names, the one from which base64 or hex, possibly
+    // split, runs to the end, and whether it is base64.
+    let (names, joined, base64): (&[usize], _, _) = match record_type {
+        "DNSKEY" | "CDNSKEY" | "KEY" => (&[], Some(3), true),
+        "DS" | "CDS" => (&[], Some(3), false),
+        "RRSIG" | "SIG" => (&[7], Some(8), true),
+        "NSEC" => (&[0], None, false),
+        "NSEC3" | "NSEC3PARAM" => (&[], None, false),
+        _ => return Ok(fields.join(" ")),
+    };
+    let mut normal = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        if joined == Some(i) {
+            let rest = fields[i..].concat();
+            normal.push(if base64 { rest } else { rest.to_uppercase() });
+            break;
+        }
+        normal.push(if names.contains(&i) {
+            zonefile::name(&absolute(field, origin)?.to_lowercase())
+        } else {
+            field.to_uppercase()
+        });
+    }
+    Ok(normal.join(" "))
+}
+
+/// How a record set differs between two zones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(RecordSet),
+    Removed(RecordSet),
+    Changed { old: RecordSet, new: RecordSet },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub owner: Name,
+    pub record_type: String,
+    pub change: Change,
+}
+
+/// The record sets added, removed and changed from `old` to `new`, in
+/// the order of their owner names.
+///
+/// The serial of the SOA record is left out, it is reported on its own.
+pub fn diff(old: &Zone, new: &Zone) -> Vec<Difference> {
+    let keys = old
+        .rrsets
+        .keys()
+        .chain(new.rrsets.keys())
+        .collect::<BTreeSet<_>>();
+    keys.into_iter()
+        .filter_map(|(owner, record_type)| {
+            let key = (owner.clone(), record_type.clone());
+            let change = match (old.rrsets.get(&key), new.rrsets.get(&key)) {
+                (Some(old), None) => Change::Removed(old.clone()),
+                (None, Some(new)) => Change::Added(new.clone()),
+                (Some(old), Some(new)) if !same(record_type, old, new) => Change::Changed {
+                    old: old.clone(),
+                    new: new.clone(),
+                },
+                _ => return None,
+            };
+            Some(Difference {
+                owner: owner.clone(),
+                record_type: record_type.clone(),
+                change,
+            })
+        })
+        .collect()
+}
+
+/// Whether two record sets are the same, but for the serial of a SOA
+/// record.
+fn same(record_type: &str, old: &RecordSet, new: &RecordSet) -> bool {
+    if record_type != "SOA" {
+        return old == new;
+    }
+    let without_serial = |rrset: &RecordSet| {
+        rrset
+            .rdata
+            .iter()
+            .map(|rdata| {
+                let mut fields = rdata.split_whitespace().collect::<Vec<_>>();
+                if fields.len() > 2 {
+                    fields.remove(2);
+                }
+                fields.join(" ")
+            })
+            .collect::<BTreeSet<_>>()
+    };
+    old.ttl == new.ttl && without_serial(old) == without_serial(new)
+}
+
+/// The serials of both zones and their differences grouped by owner name.
+pub fn report(old: &Zone, new: &Zone, differences: &[Difference]) -> String {
+    let serial = |zone: &Zone| {
+        zone.serial()
+            .map(|serial| serial.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let mut report = format!("Old serial {}\nNew serial {}", serial(old), serial(new));
+    let mut owner = None;
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for difference in differences {
+        if owner != Some(&difference.owner) {
+            owner = Some(&difference.owner);
+            report.push_str(&format!("\n{}", zonefile::name(&difference.owner)));
+        }
+        let lines = |prefix: &str, rrset: &RecordSet| {
+            rrset
+                .rdata
+                .iter()
+                .map(|rdata| format!("\n{} {} {}", prefix, rrset.ttl, rdata))
+                .collect::<String>()
+        };
+        match &difference.change {
+            Change::Added(rrset) => {
+                added += 1;
+                report.push_str(&lines(&format!("  + {}", difference.record_type), rrset));
+            }
+            Change::Removed(rrset) => {
+                removed += 1;
+                report.push_str(&lines(&format!("  - {}", difference.record_type), rrset));
+            }
+            Change::Changed { old, new } => {
+                changed += 1;
+                report.push_str(&format!("\n  ~ {}", difference.record_type));
+                // Records whose TTL changed are listed as removed and added.
+                let only = |a: &RecordSet, b: &RecordSet| RecordSet {
+                    ttl: a.ttl,
+                    rdata: if a.ttl == b.ttl {
+                        a.rdata.difference(&b.rdata).cloned().collect()
+                    } else {
+                        a.rdata.clone()
+                    },
+                };
+                report.push_str(&lines("    -", &only(old, new)));
+                report.push_str(&lines("    +", &only(new, old)));
+            }
+        }
+    }
+    if differences.is_empty() {
+        report.push_str("\nNo differences");
+    } else {
+        report.push_str(&format!(
+            "\n{} record sets added, {} removed and {} changed",
+            added, removed, changed
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use trust_dns_client::rr::RData;
+    use trust_dns_proto::rr::rdata::{MX, SOA};
+
+    fn domain(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::from_rdata(
+                domain("example.com."),
+                3600,
+                RData::SOA(SOA::new(
+                    domain("ns1.example.com."),
+                    domain("hostmaster.example.com."),
+                    2021,
+                    3600,
+                    600,
+                    86400,
+                    60,
+                )),
+            ),
+            Record::from_rdata(
+                domain("example.com."),
+                3600,
+                RData::MX(MX::new(10, domain("Mail.example.com."))),
+            ),
+            Record::from_rdata(
+                domain("www.example.com."),
+                300,
+                RData::A("10.0.0.1".parse().unwrap()),
+            ),
+            Record::from_rdata(
+                domain("www.example.com."),
+                300,
+                RData::A("10.0.0.2".parse().unwrap()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_master_file_matches_records() {
+        let origin = domain("example.com.");
+        let transferred = Zone::from_records(&origin, &records()).unwrap();
+        let text = "$TTL 1h ; the default\n\
+                    @ IN SOA ns1 hostmaster.example.com. (\n\
+                    \t2021 ; serial\n\
+                    \t3600 600 86400 60 )\n\
+                    \tMX 10 mail\n\
+                    www 300 IN A 10.0.0.2\n\
+                    \t300 A 10.0.0.1\n";
+        assert_eq!(
+            Zone::from_master_file(&origin, text),
+            Ok(transferred.clone())
+        );
+        assert_eq!(
+            Zone::from_master_file(&origin, &zonefile::master_file(&origin, &records())),
+            Ok(transferred)
+        );
+    }
+
+    #[test]
+    fn test_json_export() {
+        let origin = domain("example.com.");
+        let zone = Zone::from_json(
+            &origin,
+            r#"{"records": [
+                {"name": "example.com.", "type": "TXT", "ttl": 60, "data": "v=spf1 -all"},
+                {"name": "www", "type": "cname", "ttl": 60, "rdata": "web"}
+            ]}"#,
+        )
+        .unwrap();
+        let text = "$ORIGIN example.com.\n\
+                    @ 60 TXT \"v=spf1 -all\"\n\
+                    www 60 CNAME web.example.com.\n";
+        assert_eq!(Zone::from_master_file(&origin, text), Ok(zone));
+        assert!(Zone::from_json(&origin, r#"[{"name": "www"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_master_file_errors() {
+        let origin = domain("example.com.");
+        assert_eq!(
+            Zone::from_master_file(&origin, "www A 10.0.0.1\n"),
+            Err("No TTL in entry 1".to_string())
+        );
+        assert!(Zone::from_master_file(&origin, "$TTL 60\nwww A (10.0.0.1\n").is_err());
+        assert!(Zone::from_master_file(&origin, "$INCLUDE other.zone\n").is_err());
+        assert!(Zone::from_master_file(&origin, "$TTL 60\nwww A 10.0.0\n").is_err());
+        assert_eq!(ttl("1h30m"), Ok(5400));
+        assert!(ttl("99999999w").is_err());
+        assert!(ttl("1x").is_err());
+    }
+
+    #[test]
+    fn test_mnemonics_match_generic_records() {
+        use trust_dns_proto::rr::dnssec::rdata::{DNSSECRData, DNSKEY};
+        use trust_dns_proto::rr::dnssec::Algorithm;
+        use trust_dns_proto::rr::rdata::CAA;
+        let origin = domain("example.com.");
+        let transferred = Zone::from_records(
+            &origin,
+            &[
+                Record::from_rdata(
+                    domain("example.com."),
+                    3600,
+                    RData::DNSSEC(DNSSECRData::DNSKEY(DNSKEY::new(
+                        true,
+                        true,
+                        false,
+                        Algorithm::ECDSAP256SHA256,
+                        vec![1, 2, 3, 4, 5, 6],
+                    ))),
+                ),
+                Record::from_rdata(
+                    domain("example.com."),
+                    3600,
+                    RData::CAA(CAA::new_issue(
+                        false,
+                        Some(domain("ca.example.net")),
+                        vec![],
+                    )),
+                ),
+            ],
+        )
+        .unwrap();
+        let bind = "$ORIGIN example.com.\n\
+                    $TTL 3600\n\
+                    @ DNSKEY 257 3 13 ( AQID\n\
+                    \tBAUG )\n\
+                    \tCAA 0 issue \"ca.example.net\"\n";
+        assert_eq!(
+            Zone::from_master_file(&origin, bind),
+            Ok(transferred.clone())
+        );
+        let generic = "$ORIGIN example.com.\n\
+                       $TTL 3600\n\
+                       @ TYPE48 \\# 10 0101030D 010203040506\n\
+                       \tTYPE257 \\# 21 0005697373756563612E6578616D706C652E6E6574\n";
+        assert_eq!(Zone::from_master_file(&origin, generic), Ok(transferred));
+    }
+
+    #[test]
+    fn test_diff() {
+        let origin = domain("example.com.");
+        let old = Zone::from_records(&origin, &records()).unwrap();
+        let new = Zone::from_master_file(
+            &origin,
+            "$TTL 300\n\
+             @ 3600 SOA ns1 hostmaster 2022 3600 600 86400 60\n\
+             www A 10.0.0.1\n\
+             www A 10.0.0.3\n\
+             api A 10.0.0.4\n",
+        )
+        .unwrap();
+        let differences = diff(&old, &new);
+        assert_eq!(
+            differences
+                .iter()
+                .map(|difference| (difference.owner.to_ascii(), &difference.record_type))
+                .collect::<Vec<_>>(),
+            vec![
+                ("example.com.".to_string(), &"MX".to_string()),
+                ("api.example.com.".to_string(), &"A".to_string()),
+                ("www.example.com.".to_string(), &"A".to_string()),
+            ]
+        );
+        assert_eq!(
+            report(&old, &new, &differences),
+            "Old serial 2021\n\
+             New serial 2022\n\
+             example.com.\n  \
+             - MX 3600 10 mail.example.com.\n\
+             api.example.com.\n  \
+             + A 300 10.0.0.4\n\
+             www.example.com.\n  \
+             ~ A\n    \
+             - 300 10.0.0.2\n    \
+             + 300 10.0.0.3\n\
+             1 record sets added, 1 removed and 1 changed"
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,458 @@
+use crate::authoritative::Delegations;
+use crate::pool::{Limits, ResolverPool};
+use crate::rate::{Outcome, RateController};
+use crate::transport::{self, request, Transport};
+use crate::udp_engine::UdpEngine;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::{Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_resolver::config::NameServerConfigGroup;
+use trust_dns_resolver::lookup::Lookup;
+
+/// The number of times a query is retried on another name server after
+/// an error or a timeout, unless configured otherwise.
+const RETRIES: usize = 2;
+
+/// Resolves brute force candidates through the transport, spreading
+/// queries over the name server pool and pacing them with the shared
+/// rate controller.
+#[derive(Clone)]
+pub struct Engine {
+    backend: Backend,
+    transport: Transport,
+    pool: Arc<ResolverPool>,
+    rate: Arc<RateController>,
+    retries: usize,
+}
+
+/// Who brute force candidates are asked.
+#[derive(Clone)]
+enum Backend {
+    /// The name servers of the pool, asked to recurse.
+    Recursive,
+    /// The authoritative name servers, asked without recursion and
+    /// following their referrals to subzones.
+    Authoritative(Arc<Delegations>),
+}
+
+/// Why a lookup gave no records.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookupError {
+    /// The name does not exist.
+    NxDomain,
+    /// The name exists without records of the type asked for.
+    NoData,
+    ServFail,
+    Refused,
+    Timeout,
+    /// Any other failure, such as an unexpected response code or a socket
+    /// error.
+    Failed(String),
+}
+
+impl LookupError {
+    /// Whether the name server gave a definitive answer, anything else is
+    /// worth retrying on another name server.
+    pub fn is_definitive(&self) -> bool {
+        matches!(self, LookupError::NxDomain | LookupError::NoData)
+    }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            LookupError::Timeout => Outcome::Timeout,
+            error if error.is_definitive() => Outcome::Answer,
+            _ => Outcome::Error,
+        }
+    }
+}
+
+impl From<transport::Error> for LookupError {
+    /// A failure to get a response through the transport.
+    fn from(e: transport::Error) -> LookupError {
+        match e {
+            transport::Error::Timeout => LookupError::Timeout,
+            transport::Error::Failed(e) => LookupError::Failed(e),
+            e => LookupError::Failed(e.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupError::NxDomain => write!(f, "NXDOMAIN"),
+            LookupError::NoData => write!(f, "NODATA"),
+            LookupError::ServFail => write!(f, "SERVFAIL"),
+            LookupError::Refused => write!(f, "REFUSED"),
+            LookupError::Timeout => write!(f, "timeout"),
+            LookupError::Failed(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+impl Engine {
+    /// Resolve through `transport`, asking the name servers to recurse.
+    pub fn recursive(
+        name_servers: &NameServerConfigGroup,
+        transport: Transport,
+        limits: Limits,
+        rate: Arc<RateController>,
+    ) -> Engine {
+        Engine {
+            backend: Backend::Recursive,
+            transport,
+            pool: Arc::new(ResolverPool::new(server_addrs(name_servers), limits)),
+            rate,
+            retries: RETRIES,
+        }
+    }
+
+    /// Like [`Engine::recursive`], with the UDP queries written onto a
+    /// shared pool of sockets rather than a socket each.
+    pub async fn udp(
+        name_servers: &NameServerConfigGroup,
+        transport: Transport,
+        limits: Limits,
+        rate: Arc<RateController>,
+    ) -> Result<Engine, String> {
+        let sockets = bind_sockets(&server_addrs(name_servers)).await?;
+        Ok(Engine::recursive(
+            name_servers,
+            transport.with_sockets(sockets),
+            limits,
+            rate,
+        ))
+    }
+
+    /// Query the authoritative name servers at `addrs` directly, without
+    /// recursion, following referrals through the `delegations`.  UDP
+    /// queries go out on a shared pool of sockets.
+    pub async fn authoritative(
+        addrs: Vec<SocketAddr>,
+        transport: Transport,
+        delegations: Delegations,
+        limits: Limits,
+        rate: Arc<RateController>,
+    ) -> Result<Engine, String> {
+        let sockets = bind_sockets(&addrs).await?;
+        Ok(Engine {
+            backend: Backend::Authoritative(Arc::new(delegations)),
+            transport: transport.with_sockets(sockets),
+            pool: Arc::new(ResolverPool::new(addrs, limits)),
+            rate,
+            retries: RETRIES,
+        })
+    }
+
+    /// Retry failed queries `retries` times, each time on another name
+    /// server when there is one.
+    pub fn with_retries(self, retries: usize) -> Engine {
+        Engine { retries, ..self }
+    }
+
+    /// The current rate in queries per second.
+    pub fn rate(&self) -> f64 {
+        self.rate.rate()
+    }
+
+    /// A table of the queries each name server handled.
+    pub fn report(&self) -> String {
+        self.pool.report()
+    }
+
+    /// Look up the records of `record_type` for `name`.
+    pub async fn lookup(&self, name: Name, record_type: RecordType) -> Result<Lookup, LookupError> {
+        self.resolve(name, Some(record_type)).await
+    }
+
+    /// Look up the addresses of `name`, IPv4 first and IPv6 when there
+    /// are no IPv4 addresses.
+    pub async fn lookup_ip(&self, name: Name) -> Result<Lookup, LookupError> {
+        self.resolve(name, None).await
+    }
+
+    /// Resolve `name` on the servers picked by the pool, moving on to
+    /// another server when one fails.  Without a record type the
+    /// addresses of `name` are looked up.
+    async fn resolve(
+        &self,
+        name: Name,
+        record_type: Option<RecordType>,
+    ) -> Result<Lookup, LookupError> {
+        let mut tried = vec![];
+        let mut error = LookupError::Timeout;
+        for _ in 0..=self.retries {
+            let index = self.pool.select(&tried);
+            tried.push(index);
+            let server = self.pool.server(index);
+            let _slot = server.acquire().await;
+            // Only the round trips count towards the server's latency,
+            // not the time spent waiting on the rate.
+            let mut latency = Duration::default();
+            let result = self
+                .attempt(server.addr(), &name, record_type, &mut latency)
+                .await;
+            let outcome = match &result {
+                Ok(_) => Outcome::Answer,
+                Err(e) => e.outcome(),
+            };
+            self.pool.record(index, outcome, latency);
+            self.rate.record(&server.addr().to_string(), outcome);
+            match result {
+                Err(e) if !e.is_definitive() => error = e,
+                result => return result,
+            }
+        }
+        Err(error)
+    }
+
+    /// Ask `server` for the records of `name`, adding the time its round
+    /// trips took to `latency`.
+    async fn attempt(
+        &self,
+        server: SocketAddr,
+        name: &Name,
+        record_type: Option<RecordType>,
+        latency: &mut Duration,
+    ) -> Result<Lookup, LookupError> {
+        let record_types = match record_type {
+            Some(record_type) => vec![record_type],
+            None => vec![RecordType::A, RecordType::AAAA],
+        };
+        for record_type in record_types {
+            let query = Query::query(name.clone(), record_type);
+            let response = match &self.backend {
+                Backend::Authoritative(delegations) => {
+                    delegations
+                        .query(&self.transport, &self.rate, &query, server, latency)
+                        .await?
+                }
+                Backend::Recursive => {
+                    self.rate.wait().await;
+                    let sent = Instant::now();
+                    let response = self
+                        .transport
+                        .query(&server.into(), request(query.clone(), true), None)
+                        .await;
+                    *latency += sent.elapsed();
+                    response?
+                }
+            };
+            match response.response_code() {
+                ResponseCode::NoError if !response.answers().is_empty() => {
+                    return Ok(Lookup::new_with_max_ttl(
+                        query,
+                        Arc::new(response.answers().to_vec()),
+                    ))
+                }
+                ResponseCode::NoError => continue,
+                ResponseCode::NXDomain => return Err(LookupError::NxDomain),
+                ResponseCode::ServFail => return Err(LookupError::ServFail),
+                ResponseCode::Refused => return Err(LookupError::Refused),
+                code => return Err(LookupError::Failed(format!("{} from {}", code, server))),
+            }
+        }
+        Err(LookupError::NoData)
+    }
+}
+
+/// Bind the shared sockets UDP queries to `addrs` go out on.
+async fn bind_sockets(addrs: &[SocketAddr]) -> Result<UdpEngine, String> {
+    UdpEngine::new(addrs)
+        .await
+        .map_err(|e| format!("Failed to bind the UDP engine sockets: {}", e))
+}
+
+/// The distinct name server addresses of the group, a server is listed
+/// once for each protocol.
+fn server_addrs(name_servers: &NameServerConfigGroup) -> Vec<SocketAddr> {
+    let mut addrs = vec![];
+    for name_server in name_servers.iter() {
+        if !addrs.contains(&name_server.socket_addr) {
+            addrs.push(name_server.socket_addr);
+        }
+    }
+    addrs
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::transport::Policy;
+    use crate::udp_engine::tests::stand_in_server;
+    use futures::prelude::*;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use trust_dns_resolver::config::{NameServerConfig, Protocol};
+
+    /// UDP queries giving up on the stand-in server quickly.
+    pub fn transport() -> Transport {
+        let mut transport = Transport::new(Policy::Udp);
+        transport.timeouts.query = Duration::from_millis(500);
+        transport
+    }
+
+    async fn engines() -> (Engine, Engine) {
+        let server = stand_in_server().await;
+        let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port());
+        let rate = Arc::new(RateController::new(1_000_000, 1_000_000));
+        let recursive =
+            Engine::recursive(&name_servers, transport(), Limits::default(), rate.clone());
+        let udp = Engine::udp(&name_servers, transport(), Limits::default(), rate)
+            .await
+            .unwrap();
+        (recursive, udp)
+    }
+
+    #[tokio::test]
+    async fn test_engines_agree() {
+        let (recursive, udp) = engines().await;
+        for engine in [recursive, udp].iter() {
+            let name = Name::from_str("www.example.com.").unwrap();
+            let lookup = engine.lookup_ip(name.clone()).await.unwrap();
+            assert_eq!(lookup.query().name(), &name);
+            assert_eq!(lookup.record_iter().count(), 1);
+            let missing = Name::from_str("nx.example.com.").unwrap();
+            assert!(engine.lookup_ip(missing).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_engines_classify_failures() {
+        let (recursive, udp) = engines().await;
+        for engine in [recursive, udp].iter() {
+            let engine = engine.clone().with_retries(0);
+            let missing = Name::from_str("nx.example.com.").unwrap();
+            assert_eq!(
+                engine.lookup_ip(missing).await.unwrap_err(),
+                LookupError::NxDomain
+            );
+            let empty = Name::from_str("www.example.com.").unwrap();
+            assert_eq!(
+                engine.lookup(empty, RecordType::MX).await.unwrap_err(),
+                LookupError::NoData
+            );
+            let failing = Name::from_str("fail.example.com.").unwrap();
+            assert_eq!(
+                engine.lookup_ip(failing).await.unwrap_err(),
+                LookupError::ServFail
+            );
+            let dropped = Name::from_str("drop.example.com.").unwrap();
+            assert_eq!(
+                engine.lookup_ip(dropped).await.unwrap_err(),
+                LookupError::Timeout
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_engine_follows_the_transport() {
+        // Nothing listens for TCP on the stand-in server's port.
+        let server = stand_in_server().await;
+        let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port());
+        let rate = Arc::new(RateController::new(1_000_000, 1_000_000));
+        let engine = Engine::udp(
+            &name_servers,
+            transport().with_policy(Policy::Tcp),
+            Limits::default(),
+            rate,
+        )
+        .await
+        .unwrap()
+        .with_retries(0);
+        let name = Name::from_str("www.example.com.").unwrap();
+        assert_eq!(
+            engine.lookup_ip(name).await.unwrap_err(),
+            LookupError::Failed("connection refused".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_latency_leaves_out_the_rate() {
+        let server = stand_in_server().await;
+        let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port());
+        // Two queries a second, so the last of four waits over a second.
+        let rate = Arc::new(RateController::new(2, 2));
+        let engine = Engine::recursive(&name_servers, transport(), Limits::default(), rate);
+        let lookups = (0..4).map(|i| {
+            let name = Name::from_str(&format!("host{}.example.com.", i)).unwrap();
+            engine.lookup(name, RecordType::A)
+        });
+        for lookup in future::join_all(lookups).await {
+            assert!(lookup.is_ok());
+        }
+        let report = engine.report();
+        let latency = report
+            .lines()
+            .nth(1)
+            .unwrap()
+            .split_whitespace()
+            .nth(5)
+            .unwrap();
+        let latency = latency.trim_end_matches("ms").parse::<f64>().unwrap();
+        assert!(latency < 200.0, "latency {}ms", latency);
+    }
+
+    #[tokio::test]
+    async fn test_engine_retries_on_another_server() {
+        let dead = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let live = stand_in_server().await;
+        let name_servers = NameServerConfigGroup::from(
+            [dead, live]
+                .iter()
+                .map(|addr| NameServerConfig {
+                    socket_addr: *addr,
+                    protocol: Protocol::Udp,
+                    tls_dns_name: None,
+                })
+                .collect::<Vec<_>>(),
+        );
+        let rate = Arc::new(RateController::new(1_000_000, 1_000_000));
+        let engine = Engine::udp(&name_servers, transport(), Limits::default(), rate)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            let name = Name::from_str("www.example.com.").unwrap();
+            assert!(engine.lookup_ip(name).await.is_ok());
+        }
+        let report = engine.report();
+        assert!(report.contains(&live.to_string()));
+    }
+
+    /// Compare the throughput of a socket per query and the shared sockets
+    /// against a local stand-in server, run with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_engines() {
+        let (socket, udp) = engines().await;
+        for (label, engine) in [("socket", socket), ("udp", udp)].iter() {
+            let names = 20_000;
+            let start = Instant::now();
+            let resolved = stream::iter(0..names)
+                .map(|i| {
+                    let name = Name::from_str(&format!("{}{}.example.com.", label, i)).unwrap();
+                    engine.lookup_ip(name)
+                })
+                .buffer_unordered(1000)
+                .filter(|lookup| future::ready(lookup.is_ok()))
+                .count()
+                .await;
+            let elapsed = start.elapsed();
+            println!(
+                "{}: {} of {} names in {:?}, {:.0} queries/s",
+                label,
+                resolved,
+                names,
+                elapsed,
+                names as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -1,24 +1,54 @@
+mod alter;
+mod authoritative;
 mod brute;
+mod diff;
+mod engine;
+mod pattern;
+mod pool;
+mod progress;
+mod rate;
 mod resolver;
+mod takeover;
+mod tls;
+mod transfer;
+mod transport;
+mod tsig;
+mod udp_engine;
+mod vet;
+mod wildcard;
+mod wordlist;
+mod zonefile;
 
-use clap::{App, Arg, ArgMatches, Values};
+use brute::Tally;
+use clap::{App, Arg, ArgGroup, ArgMatches, Values};
 use colored::*;
+use engine::Engine;
+use futures::channel::{mpsc, oneshot};
 use futures::prelude::*;
 use futures::stream;
-use std::net::IpAddr;
+use pool::Limits;
+use progress::Progress;
+use rate::RateController;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
-use stream_throttle::{ThrottlePool, ThrottleRate};
+use takeover::Fingerprints;
 use trust_dns_client::rr::rdata::caa::Value;
 use trust_dns_client::rr::rdata::DNSSECRecordType;
-use trust_dns_client::rr::RecordType;
+use trust_dns_client::rr::{Name, Record, RecordType};
 use trust_dns_proto::rr::dnssec::rdata::DNSSECRData;
 use trust_dns_proto::rr::rdata;
 use trust_dns_proto::rr::record_data::RData;
 use trust_dns_resolver::config::NameServerConfigGroup;
-use trust_dns_resolver::config::*;
 use trust_dns_resolver::error::ResolveError;
+use trust_dns_resolver::system_conf::read_system_conf;
 use trust_dns_resolver::{AsyncResolver, TokioAsyncResolver};
+use wordlist::Wordlist;
+
+/// The port authoritative name servers listen on.
+const DNS_PORT: u16 = 53;
 
 #[tokio::main]
 async fn main() {
@@ -32,9 +62,12 @@ async fn main() {
                 .required(true)
                 .takes_value(true)
                 .index(1)
-                .possible_values(&["brute", "axfr", "dnssec"])
+                .possible_values(&["brute", "axfr", "ixfr", "dnssec", "diff"])
                 .default_value("axfr")
-                .requires_if("brute", "SUBDOMAINS"),
+                .requires_if("brute", "CANDIDATES")
+                .requires_if("ixfr", "IXFR_START")
+                .requires_if("diff", "OLD_ZONE")
+                .requires_if("diff", "NEW_ZONE"),
         )
         .arg(
             Arg::with_name("DOMAIN")
@@ -47,31 +80,283 @@ async fn main() {
             Arg::with_name("SUBDOMAINS")
                 .short("s")
                 .long("subdomains")
-                .help("The subdomains file to enumerate")
+                .help("The subdomains files to enumerate, plain, gzip or zstd, - for stdin")
                 .required(false)
                 .takes_value(true)
-                .validator(validate_subdomain_file),
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_wordlist_source),
+        )
+        .arg(
+            Arg::with_name("PATTERN")
+                .long("pattern")
+                .help("A pattern to generate the subdomains from, e.g. web[0-9][0-9] or {dev,prod}-?l?d")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_pattern),
+        )
+        .group(ArgGroup::with_name("CANDIDATES").args(&["SUBDOMAINS", "PATTERN"]))
+        .arg(
+            Arg::with_name("SERIAL")
+                .long("serial")
+                .help("The SOA serial an ixfr asks for the changes since")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_serial),
+        )
+        .arg(
+            Arg::with_name("FROM_ZONE")
+                .long("from-zone")
+                .help("A zone saved from an earlier run, an ixfr asks for the changes since its SOA serial")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_saved_zone),
+        )
+        .group(ArgGroup::with_name("IXFR_START").args(&["SERIAL", "FROM_ZONE"]))
+        .arg(
+            Arg::with_name("OLD_ZONE")
+                .long("old")
+                .help("The zone a diff compares from: axfr, axfr:<name server>, a master file or a JSON export")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_zone_source),
+        )
+        .arg(
+            Arg::with_name("NEW_ZONE")
+                .long("new")
+                .help("The zone a diff compares to: axfr, axfr:<name server>, a master file or a JSON export")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_zone_source),
+        )
+        .arg(
+            Arg::with_name("TSIG_KEY")
+                .long("tsig-key")
+                .help("A BIND key file with the TSIG key axfr and ixfr requests are signed with")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_tsig_key),
+        )
+        .arg(
+            Arg::with_name("ZONE_FILE")
+                .long("zone-file")
+                .help("Write the records found to a master file, ixfr changes are left out")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TRANSPORT")
+                .long("transport")
+                .help("How the name servers are queried, udp-tcp asks again over tcp when the answer is truncated; axfr and ixfr go over tls for tls and tcp otherwise")
+                .required(false)
+                .possible_values(&["udp", "tcp", "udp-tcp", "tls", "https"])
+                .default_value("udp-tcp"),
+        )
+        .arg(
+            Arg::with_name("EDNS_SIZE")
+                .long("edns-size")
+                .help("The UDP payload size advertised with EDNS, 0 sends queries without EDNS")
+                .required(false)
+                .default_value("1232")
+                .validator(validate_edns_size),
+        )
+        .arg(
+            Arg::with_name("DNSSEC_OK")
+                .long("dnssec-ok")
+                .help("Set the DO bit, asking for the DNSSEC records of the answers"),
+        )
+        .arg(
+            Arg::with_name("CHECKING_DISABLED")
+                .long("checking-disabled")
+                .help("Set the CD bit, asking resolvers not to validate the answers"),
+        )
+        .arg(
+            Arg::with_name("HTTPS_PATH")
+                .long("https-path")
+                .help("The path DNS over HTTPS queries are posted to")
+                .required(false)
+                .default_value("/dns-query")
+                .validator(validate_https_path),
+        )
+        .arg(
+            Arg::with_name("HTTPS_PORT")
+                .long("https-port")
+                .help("The port DNS over HTTPS queries are posted to")
+                .required(false)
+                .default_value("443")
+                .validator(validate_name_server_port),
+        )
+        .arg(
+            Arg::with_name("XOT")
+                .long("xot")
+                .help("Try axfr and ixfr over TLS too, reporting where it allows otherwise than clear text"),
+        )
+        .arg(
+            Arg::with_name("TLS_PORT")
+                .long("tls-port")
+                .help("The port queries and zone transfers over TLS are sent to")
+                .required(false)
+                .default_value("853")
+                .validator(validate_name_server_port),
+        )
+        .arg(
+            Arg::with_name("TLS_NAME")
+                .long("tls-name")
+                .help("The name the TLS certificates are checked against and sent as SNI, the name server's own by default")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TLS_CA")
+                .long("tls-ca")
+                .help("A PEM bundle of the CAs TLS certificates are checked against, the web PKI roots by default")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TLS_CLIENT_CERT")
+                .long("tls-client-cert")
+                .help("A PEM client certificate chain for name servers that ask for one")
+                .required(false)
+                .takes_value(true)
+                .requires("TLS_CLIENT_KEY"),
+        )
+        .arg(
+            Arg::with_name("TLS_CLIENT_KEY")
+                .long("tls-client-key")
+                .help("The PEM private key of the client certificate")
+                .required(false)
+                .takes_value(true)
+                .requires("TLS_CLIENT_CERT"),
         )
         .arg(
             Arg::with_name("RATE")
                 .short("r")
                 .long("rate")
-                .help("The number of queries per second to issue")
+                .help("The highest number of queries per second to issue, the rate is lowered while resolvers fail or time out")
                 .required(false)
                 .default_value("100")
                 .takes_value(true)
                 .validator(validate_rate),
         )
+        .arg(
+            Arg::with_name("MIN_RATE")
+                .long("min-rate")
+                .help("The lowest number of queries per second the rate is lowered to")
+                .required(false)
+                .default_value("10")
+                .takes_value(true)
+                .validator(validate_rate),
+        )
         .arg(
             Arg::with_name("CONCURRENCY")
                 .short("c")
                 .long("concurrency")
-                .help("The number of concurrent requests")
+                .help("The number of concurrent requests, and of name servers axfr, ixfr and dnssec work on at once")
                 .required(false)
                 .default_value("1000")
                 .takes_value(true)
+                .validator(validate_concurrency),
+        )
+        .arg(
+            Arg::with_name("CONNECT_TIMEOUT")
+                .long("connect-timeout")
+                .help("The seconds to wait to connect to each name server")
+                .required(false)
+                .default_value("5")
+                .validator(validate_timeout),
+        )
+        .arg(
+            Arg::with_name("QUERY_TIMEOUT")
+                .long("query-timeout")
+                .help("The seconds to wait for each answer from the name servers")
+                .required(false)
+                .default_value("5")
+                .validator(validate_timeout),
+        )
+        .arg(
+            Arg::with_name("RESOLVER_RATE")
+                .long("resolver-rate")
+                .help("The highest number of queries per second to send to a single name server")
+                .required(false)
+                .takes_value(true)
                 .validator(validate_rate),
         )
+        .arg(
+            Arg::with_name("RESOLVER_CONCURRENCY")
+                .long("resolver-concurrency")
+                .help("The number of concurrent requests to a single name server")
+                .required(false)
+                .takes_value(true)
+                .validator(validate_concurrency),
+        )
+        .arg(
+            Arg::with_name("RETRIES")
+                .long("retries")
+                .help("The number of times a query that timed out or failed with SERVFAIL or REFUSED is retried on another name server")
+                .required(false)
+                .default_value("2")
+                .takes_value(true)
+                .validator(validate_retries),
+        )
+        .arg(
+            Arg::with_name("DEPTH")
+                .short("d")
+                .long("depth")
+                .help("The number of subdomain levels to brute force below the domain")
+                .required(false)
+                .default_value("1")
+                .takes_value(true)
+                .validator(validate_depth),
+        )
+        .arg(
+            Arg::with_name("ENGINE")
+                .long("engine")
+                .help("How brute force queries go out, socket binds a socket for each and udp sends the UDP queries over a shared pool of sockets")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["socket", "udp"])
+                .default_value("socket"),
+        )
+        .arg(
+            Arg::with_name("AUTHORITATIVE")
+                .long("authoritative")
+                .help("Brute force against the authoritative name servers of the domain without recursion, following referrals to subzones")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("ALTER")
+                .long("alter")
+                .help("Resolve alterations of the names found by brute or axfr")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("ALTER_WORDS")
+                .long("alter-words")
+                .help("The words file used for alterations")
+                .required(false)
+                .takes_value(true)
+                .requires("ALTER")
+                .validator(validate_subdomain_file),
+        )
+        .arg(
+            Arg::with_name("TAKEOVER")
+                .long("takeover")
+                .help("Check the CNAMEs in the results for subdomain takeovers")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("FINGERPRINTS")
+                .long("fingerprints")
+                .help("The fingerprints file of the services open to takeovers, replacing the bundled fingerprints")
+                .required(false)
+                .takes_value(true)
+                .requires("TAKEOVER")
+                .validator(validate_fingerprints),
+        )
         .arg(
             Arg::with_name("NAMES_SERVERS")
                 .short("n")
@@ -82,6 +367,34 @@ async fn main() {
                 .multiple(true)
                 .value_delimiter(","),
         )
+        .arg(
+            Arg::with_name("KNOWN_ANSWERS")
+                .long("known-answer")
+                .help("A name and an address it resolves to, e.g. www.example.com=93.184.216.34, name servers answering otherwise are removed")
+                .takes_value(true)
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_known_answer),
+        )
+        .arg(
+            Arg::with_name("TRUSTED_NS")
+                .long("trusted-ns")
+                .help("The name server the answers of the other name servers are compared to, the system resolver by default")
+                .takes_value(true)
+                .required(false)
+                .validator(validate_ip),
+        )
+        .arg(
+            Arg::with_name("KEEP_UNTRUSTED_NS")
+                .long("keep-untrusted-ns")
+                .help("Only warn about name servers failing the checks instead of removing them"),
+        )
+        .arg(
+            Arg::with_name("NO_NS_CHECKS")
+                .long("no-ns-checks")
+                .help("Use the name servers without checking their answers"),
+        )
         .arg(
             Arg::with_name("NAME_SERVER_PORT")
                 .short("p")
@@ -115,14 +428,14 @@ async fn main() {
         )
         .get_matches();
 
-    println!(
+    progress::note(format!(
         "[{}] Use with caution.  You are responsible for your actions.",
         "WRN".yellow()
-    );
-    println!(
+    ));
+    progress::note(format!(
         "[{}] Developers assume no liability and are not responsible for any misuse or damage.",
         "WRN".yellow()
-    );
+    ));
 
     let operation = command.value_of("OPERATION").expect("operation expected");
     let domain = command.value_of("DOMAIN").expect("domain expected");
@@ -138,79 +451,452 @@ async fn main() {
         .parse::<usize>()
         .unwrap();
 
-    let rate = ThrottleRate::new(query_per_sec, Duration::from_secs(1));
-    let pool = ThrottlePool::new(rate);
+    let min_query_per_sec = command
+        .value_of("MIN_RATE")
+        .expect("min rate expected")
+        .parse::<usize>()
+        .unwrap();
 
-    let resolver_config =
-        ResolverConfig::from_parts(None, vec![], fetch_resolve_config(&command).await);
+    let rate = Arc::new(RateController::new(query_per_sec, min_query_per_sec));
 
-    let resolver = TokioAsyncResolver::tokio(
-        resolver_config,
-        ResolverOpts {
-            preserve_intermediates: true,
-            ..ResolverOpts::default()
+    let limits = Limits {
+        rate: command
+            .value_of("RESOLVER_RATE")
+            .map(|rate| rate.parse::<usize>().unwrap()),
+        concurrency: command
+            .value_of("RESOLVER_CONCURRENCY")
+            .map(|concurrency| concurrency.parse::<usize>().unwrap()),
+    };
+
+    let transport = match query_transport(&command) {
+        Ok(transport) => transport,
+        Err(e) => {
+            progress::note(format!("[{}] {}", "ERR".red(), e));
+            return;
+        }
+    };
+    // Read before any work is done, for a bad file not to end a long run.
+    let fingerprints = match command.value_of("FINGERPRINTS") {
+        Some(path) => match Fingerprints::from_file(path) {
+            Ok(fingerprints) => fingerprints,
+            Err(e) => {
+                progress::note(format!("[{}] {}", "ERR".red(), e));
+                return;
+            }
         },
+        None => Fingerprints::bundled(),
+    };
+    let name_servers = fetch_resolve_config(&command, &transport, rate.clone()).await;
+    let authoritative = command.is_present("AUTHORITATIVE") && operation == "brute";
+    let engine = if authoritative {
+        authoritative_engine(domain, &name_servers, &transport, limits, rate.clone()).await
+    } else if command.value_of("ENGINE") == Some("udp") {
+        Engine::udp(&name_servers, transport.clone(), limits, rate.clone()).await
+    } else {
+        Ok(Engine::recursive(
+            &name_servers,
+            transport.clone(),
+            limits,
+            rate.clone(),
+        ))
+    }
+    .expect("Failed to create the resolution engine")
+    .with_retries(
+        command
+            .value_of("RETRIES")
+            .expect("retries expected")
+            .parse::<usize>()
+            .unwrap(),
     );
+    let tally = Arc::new(Tally::new());
+    let key = command
+        .value_of("TSIG_KEY")
+        .map(|path| tsig::Key::from_file(path).expect("TSIG key expected"));
+    let transports = transfer_transports(&command, &transport);
 
-    let res = resolver.await.expect("Failed to connect to resolver");
-
-    let records = if operation == "axfr" {
-        if let Some(nameservers) = command.values_of("NAMES_SERVERS") {
-            let name_server_port = command
-                .value_of("NAME_SERVER_PORT")
-                .expect("Port expected")
-                .parse::<u16>()
-                .expect("Port expected to be a number");
-            let ns_ips = validate_name_servers(nameservers).await;
-            resolver::query(
+    if operation == "diff" {
+        let mut zones = vec![];
+        for source in &["OLD_ZONE", "NEW_ZONE"] {
+            let source = command.value_of(source).expect("zone expected");
+            match load_zone(
+                &command,
                 domain,
-                &ns_ips,
-                name_server_port,
+                source,
+                &engine,
                 concurrency,
-                RecordType::AXFR,
+                key.as_ref(),
+                &transports,
             )
             .await
-        } else {
-            vec![]
+            {
+                Ok(zone) => zones.push(zone),
+                Err(e) => {
+                    progress::note(format!("[{}] {}", "ERR".red(), e));
+                    std::process::exit(2);
+                }
+            }
         }
+        let differences = diff::diff(&zones[0], &zones[1]);
+        println!("*********************");
+        println!("Zone differences");
+        println!("*********************");
+        println!("{}", diff::report(&zones[0], &zones[1], &differences));
+        std::process::exit(if differences.is_empty() { 0 } else { 1 });
+    }
+
+    let mut transfers = None;
+    let mut incremental = vec![];
+    let mut records = if operation == "axfr" {
+        let ns = operation_name_servers(&command, domain, &engine).await;
+        // The records are printed as they arrive, the transfers reported
+        // once they are all done.
+        let (found, received) = mpsc::unbounded();
+        let (done, finished) = oneshot::channel();
+        let (domain, key, transports) = (domain.to_string(), key.clone(), transports.clone());
+        tokio::spawn(async move {
+            let transfers =
+                transfer::transfer(&domain, &ns, concurrency, key.as_ref(), &transports, &found);
+            let _ = done.send(transfers.await);
+        });
+        transfers = Some(finished);
+        received
+            .map(|(ns, policy, record)| (transfer_label(&ns, policy), record))
+            .boxed()
+    } else if operation == "ixfr" {
+        let serial = match command.value_of("SERIAL") {
+            Some(serial) => serial.parse::<u32>().expect("serial expected"),
+            None => saved_zone_serial(command.value_of("FROM_ZONE").expect("zone expected"))
+                .expect("serial expected"),
+        };
+        let ns = operation_name_servers(&command, domain, &engine).await;
+        incremental = transfer::incremental_transfer(
+            domain,
+            &ns,
+            serial,
+            concurrency,
+            key.as_ref(),
+            &transports,
+        )
+        .await;
+        let records = incremental
+            .iter()
+            .flat_map(|transfer| {
+                let label = transfer_label(&transfer.name_server, transfer.policy);
+                let records = match &transfer.changes {
+                    Some(transfer::Changes::Incremental(differences)) => differences
+                        .iter()
+                        .flat_map(|difference| {
+                            let removed = difference
+                                .removed
+                                .iter()
+                                .map(|record| (format!("{}-", label), record.clone()));
+                            let added = difference
+                                .added
+                                .iter()
+                                .map(|record| (format!("{}+", label), record.clone()));
+                            removed.chain(added).collect::<Vec<_>>()
+                        })
+                        .collect(),
+                    Some(transfer::Changes::FullZone(records)) => records
+                        .iter()
+                        .map(|record| (label.clone(), record.clone()))
+                        .collect(),
+                    _ => vec![],
+                };
+                records
+            })
+            .collect::<Vec<_>>();
+        stream::iter(records).boxed()
     } else if operation == "dnssec" {
-        if let Some(nameservers) = command.values_of("NAMES_SERVERS") {
-            let name_server_port = command
-                .value_of("NAME_SERVER_PORT")
-                .expect("Port expected")
-                .parse::<u16>()
-                .expect("Port expected to be a number");
-            let ns_ips = validate_name_servers(nameservers).await;
-            resolver::query_udp(
-                domain,
-                &ns_ips,
-                name_server_port,
-                concurrency,
-                RecordType::DNSSEC(DNSSECRecordType::DNSKEY),
-            )
-            .await
-        } else {
-            vec![]
-        }
+        let ns = operation_name_servers(&command, domain, &engine).await;
+        let records = resolver::query(
+            domain,
+            &ns,
+            concurrency,
+            RecordType::DNSSEC(DNSSECRecordType::DNSKEY),
+            &transport,
+        )
+        .await;
+        stream::iter(records)
+            .map(|(ns, record)| (format!("[{}] ", ns), record))
+            .boxed()
     } else if operation == "brute" {
-        let subdomains_file = command.value_of("SUBDOMAINS").expect("subdomains expected");
-        brute::brute_force_domain(domain, subdomains_file, pool, &res, concurrency).await
+        let depth = command
+            .value_of("DEPTH")
+            .expect("depth expected")
+            .parse::<usize>()
+            .unwrap();
+        let wordlist = match command.value_of("PATTERN") {
+            Some(pattern) => Wordlist::Pattern(pattern.parse().expect("pattern expected")),
+            None => Wordlist::from_paths(
+                command
+                    .values_of("SUBDOMAINS")
+                    .expect("subdomains expected"),
+                depth > 1,
+            )
+            .expect("Could not read subdomains from stdin"),
+        };
+        brute::brute_force_domain(
+            domain,
+            wordlist,
+            engine.clone(),
+            concurrency,
+            depth,
+            tally.clone(),
+        )
+        .flat_map(|lookup| {
+            stream::iter(
+                lookup
+                    .record_iter()
+                    .map(|record| (String::new(), record.clone()))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .boxed()
+    } else {
+        progress::note(format!("Unkown operation: {}", operation));
+        stream::empty().boxed()
+    };
+
+    let alter = command.is_present("ALTER") && operation != "dnssec";
+    let mut known = HashSet::new();
+    let takeover = command.is_present("TAKEOVER") && operation != "dnssec";
+    let mut cnames = vec![];
+    // An ixfr gives changes, not the records of a zone.
+    let zone_file = command
+        .value_of("ZONE_FILE")
+        .filter(|_| operation != "ixfr");
+    let mut zone = vec![];
+
+    let progress = if operation == "brute" || alter {
+        Some(Progress::start(tally.clone(), engine.clone()))
     } else {
-        println!("Unkown operation: {}", operation);
-        vec![]
+        None
+    };
+    let clear_progress = || {
+        if let Some(progress) = &progress {
+            progress.clear();
+        }
     };
 
     println!("*********************");
     println!("Results");
     println!("*********************");
-    for record in records {
-        println!(
-            "{}:{}:{}",
-            record.name().to_ascii(),
-            record.record_type(),
-            display_rdata(record.rdata())
+    while let Some((label, record)) = records.next().await {
+        if alter {
+            known.insert(wildcard::to_fqdn(record.name()));
+        }
+        if takeover && record.record_type() == RecordType::CNAME {
+            cnames.push(record.clone());
+        }
+        clear_progress();
+        println!("{}{}", label, display_record(&record));
+        if zone_file.is_some() {
+            zone.push(record);
+        }
+    }
+
+    if alter {
+        let words = command
+            .value_of("ALTER_WORDS")
+            .map(alter::read_words)
+            .unwrap_or_else(alter::default_words);
+        let altered = alter::alter_domain(
+            domain,
+            &known,
+            &words,
+            engine.clone(),
+            concurrency,
+            tally.clone(),
         );
+        futures::pin_mut!(altered);
+        while let Some(lookup) = altered.next().await {
+            for record in lookup.record_iter() {
+                if takeover && record.record_type() == RecordType::CNAME {
+                    cnames.push(record.clone());
+                }
+                clear_progress();
+                println!("{}", display_record(record));
+                if zone_file.is_some() {
+                    zone.push(record.clone());
+                }
+            }
+        }
+    }
+
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+
+    if let Some(path) = zone_file {
+        let origin = wildcard::to_fqdn(&domain.parse::<Name>().expect("domain expected"));
+        // Brute force does not find the apex records a zone needs to load.
+        if !zone
+            .iter()
+            .any(|record| record.record_type() == RecordType::SOA && record.name() == &origin)
+        {
+            for record_type in [RecordType::SOA, RecordType::NS].iter() {
+                if let Ok(lookup) = engine.lookup(origin.clone(), *record_type).await {
+                    zone.extend(
+                        lookup
+                            .record_iter()
+                            .filter(|record| {
+                                record.record_type() == *record_type && record.name() == &origin
+                            })
+                            .cloned(),
+                    );
+                }
+            }
+        }
+        if let Err(e) = std::fs::write(path, zonefile::master_file(&origin, &zone)) {
+            progress::note(format!("Could not write the zone file {}: {}", path, e));
+        }
+    }
+
+    if takeover {
+        // CNAME chains lead out of the zones the authoritative engine
+        // knows.
+        let checker = if authoritative {
+            Engine::recursive(&name_servers, transport.clone(), limits, rate)
+        } else {
+            engine.clone()
+        };
+        let findings =
+            takeover::check_takeovers(&cnames, &checker, &fingerprints, concurrency).await;
+        println!("*********************");
+        println!("Takeovers");
+        println!("*********************");
+        for finding in &findings {
+            println!("{}", finding);
+        }
+        println!("{} possible takeovers", findings.len());
+    }
+
+    if let Some(transfers) = transfers {
+        println!("*********************");
+        println!("Zone transfers");
+        println!("*********************");
+        println!("{}", transfer::report(&transfers.await.unwrap_or_default()));
+    }
+
+    if operation == "ixfr" {
+        println!("*********************");
+        println!("Incremental transfers");
+        println!("*********************");
+        println!("{}", transfer::incremental_report(&incremental));
+    }
+
+    if operation == "brute" || alter {
+        let unresolved = tally.unresolved();
+        if !unresolved.is_empty() {
+            println!("*********************");
+            println!("Unresolved");
+            println!("*********************");
+            for (name, error) in &unresolved {
+                println!("{}:{}", name.to_ascii(), error);
+            }
+            let unlisted = tally.counts().unresolved() - unresolved.len();
+            if unlisted > 0 {
+                println!("{} more unresolved candidates not listed", unlisted);
+            }
+        }
+        println!("{}", tally.summary());
+        println!("*********************");
+        println!("Name servers");
+        println!("*********************");
+        println!("{}", engine.report());
+    }
+}
+
+/// How the name servers are queried.
+fn query_transport(command: &ArgMatches<'_>) -> Result<transport::Transport, String> {
+    let policy = command
+        .value_of("TRANSPORT")
+        .expect("transport expected")
+        .parse::<transport::Policy>()?;
+    let mut transport = transport::Transport::new(policy);
+    let edns = command
+        .value_of("EDNS_SIZE")
+        .expect("EDNS size expected")
+        .parse::<u16>()
+        .unwrap();
+    transport.edns = Some(edns).filter(|size| *size > 0);
+    transport.dnssec_ok = command.is_present("DNSSEC_OK");
+    transport.checking_disabled = command.is_present("CHECKING_DISABLED");
+    transport.timeouts = timeouts(command);
+    transport.path = command
+        .value_of("HTTPS_PATH")
+        .expect("HTTPS path expected")
+        .to_string();
+    transport.https_port = command
+        .value_of("HTTPS_PORT")
+        .expect("HTTPS port expected")
+        .parse::<u16>()
+        .unwrap();
+    let encrypted = [transport::Policy::Tls, transport::Policy::Https].contains(&policy);
+    if command.is_present("XOT") || encrypted {
+        let client = command
+            .value_of("TLS_CLIENT_CERT")
+            .zip(command.value_of("TLS_CLIENT_KEY"));
+        transport.tls = Some(tls::Tls::from_files(
+            command.value_of("TLS_CA"),
+            client,
+            command.value_of("TLS_NAME").map(str::to_string),
+            command
+                .value_of("TLS_PORT")
+                .expect("TLS port expected")
+                .parse::<u16>()
+                .unwrap(),
+        )?);
     }
+    Ok(transport)
+}
+
+/// The transports axfr and ixfr transfer the zone through, TCP and TLS
+/// with `--xot`, otherwise the one `--transport` streams over.
+fn transfer_transports(
+    command: &ArgMatches<'_>,
+    transport: &transport::Transport,
+) -> Vec<transport::Transport> {
+    let policies = if command.is_present("XOT") {
+        vec![transport::Policy::Tcp, transport::Policy::Tls]
+    } else {
+        vec![transport.policy.stream()]
+    };
+    policies
+        .into_iter()
+        .map(|policy| transport.with_policy(policy))
+        .collect()
+}
+
+/// How long to wait for the name servers.
+fn timeouts(command: &ArgMatches<'_>) -> transport::Timeouts {
+    let seconds = |arg| {
+        let seconds = command.value_of(arg).expect("timeout expected");
+        Duration::from_secs_f64(seconds.parse::<f64>().unwrap())
+    };
+    transport::Timeouts {
+        connect: seconds("CONNECT_TIMEOUT"),
+        query: seconds("QUERY_TIMEOUT"),
+    }
+}
+
+/// The label of the records transferred from a name server, noting those
+/// that came over TLS.
+fn transfer_label(name_server: &resolver::NameServer, policy: transport::Policy) -> String {
+    match policy {
+        transport::Policy::Tls => format!("[{} {}] ", name_server, policy),
+        _ => format!("[{}] ", name_server),
+    }
+}
+
+fn display_record(record: &Record) -> String {
+    format!(
+        "{}:{}:{}",
+        record.name().to_ascii(),
+        record.record_type(),
+        display_rdata(record.rdata())
+    )
 }
 
 fn display_rdata(rdata: &RData) -> String {
@@ -261,7 +947,7 @@ fn display_rdata(rdata: &RData) -> String {
             .txt_data()
             .iter()
             .map(|x| {
-                std::str::from_utf8(&*(x))
+                std::str::from_utf8(x)
                     .map(|x| x.to_string())
                     .unwrap_or_else(|_| base64::encode(&*x))
             })
@@ -344,7 +1030,7 @@ fn displary_rr_null(null: &rdata::NULL) -> String {
                 .map(|x| x.to_string())
                 .unwrap_or_else(|_| base64::encode(x))
         })
-        .unwrap_or_else(|| "".to_string())
+        .unwrap_or_default()
 }
 
 fn display_rr_value(value: &Value) -> String {
@@ -377,6 +1063,52 @@ fn validate_subdomain_file(file: String) -> Result<(), String> {
     }
 }
 
+fn validate_wordlist_source(source: String) -> Result<(), String> {
+    if source == "-" {
+        Ok(())
+    } else {
+        validate_subdomain_file(source)
+    }
+}
+
+fn validate_pattern(pattern: String) -> Result<(), String> {
+    pattern.parse::<pattern::Pattern>().map(|_| ())
+}
+
+fn validate_fingerprints(path: String) -> Result<(), String> {
+    Fingerprints::from_file(&path).map(|_| ())
+}
+
+fn validate_serial(serial: String) -> Result<(), String> {
+    serial
+        .parse::<u32>()
+        .map(|_| ())
+        .map_err(|_| format!("Serial must be a number {}", serial))
+}
+
+fn validate_saved_zone(path: String) -> Result<(), String> {
+    saved_zone_serial(&path).map(|_| ())
+}
+
+fn saved_zone_serial(path: &str) -> Result<u32, String> {
+    let zone = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read the zone {}: {}", path, e))?;
+    transfer::serial_from_zone(&zone).map_err(|e| format!("{} in {}", e, path))
+}
+
+fn validate_zone_source(source: String) -> Result<(), String> {
+    if source == "axfr" || source.starts_with("axfr:") {
+        return Ok(());
+    }
+    std::fs::metadata(&source)
+        .map(|_| ())
+        .map_err(|e| format!("Could not read the zone {}: {}", source, e))
+}
+
+fn validate_tsig_key(path: String) -> Result<(), String> {
+    tsig::Key::from_file(&path).map(|_| ())
+}
+
 fn validate_rate(rate: String) -> Result<(), String> {
     match rate.parse::<usize>() {
         Err(_) => Err(format!("Rate must be a number {}", rate)),
@@ -384,13 +1116,83 @@ fn validate_rate(rate: String) -> Result<(), String> {
     }
 }
 
+fn validate_retries(retries: String) -> Result<(), String> {
+    match retries.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Retries must be a number {}", retries)),
+    }
+}
+
+fn validate_concurrency(concurrency: String) -> Result<(), String> {
+    match concurrency.parse::<usize>() {
+        Ok(concurrency) if concurrency > 0 => Ok(()),
+        _ => Err(format!(
+            "Concurrency must be a number greater than zero {}",
+            concurrency
+        )),
+    }
+}
+
+fn validate_timeout(timeout: String) -> Result<(), String> {
+    match timeout.parse::<f64>() {
+        Ok(timeout) if timeout > 0.0 => Ok(()),
+        _ => Err(format!(
+            "Timeout must be a number of seconds greater than zero {}",
+            timeout
+        )),
+    }
+}
+
+fn validate_edns_size(size: String) -> Result<(), String> {
+    match size.parse::<u16>() {
+        Ok(size) if size == 0 || size >= 512 => Ok(()),
+        _ => Err(format!(
+            "EDNS size must be 0 or a number from 512 to 65535 {}",
+            size
+        )),
+    }
+}
+
+fn validate_https_path(path: String) -> Result<(), String> {
+    if path.starts_with('/') {
+        Ok(())
+    } else {
+        Err(format!("HTTPS path must start with / {}", path))
+    }
+}
+
+fn validate_known_answer(known_answer: String) -> Result<(), String> {
+    known_answer.parse::<vet::KnownAnswer>().map(|_| ())
+}
+
+fn validate_ip(ip: String) -> Result<(), String> {
+    match ip.parse::<IpAddr>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Not an IP address {}", ip)),
+    }
+}
+
+fn validate_depth(depth: String) -> Result<(), String> {
+    match depth.parse::<usize>() {
+        Ok(depth) if depth > 0 => Ok(()),
+        _ => Err(format!(
+            "Depth must be a number greater than zero {}",
+            depth
+        )),
+    }
+}
+
 fn validate_name_server_port(port: String) -> Result<(), String> {
     port.parse::<u16>()
         .map(|_| ())
         .map_err(|_| format!("Invalid name server port: {}", port))
 }
 
-async fn fetch_resolve_config(command: &ArgMatches<'_>) -> NameServerConfigGroup {
+async fn fetch_resolve_config(
+    command: &ArgMatches<'_>,
+    transport: &transport::Transport,
+    rate: Arc<RateController>,
+) -> NameServerConfigGroup {
     let mut config = NameServerConfigGroup::new();
     if command.is_present("GOGGLE_NS") {
         NameServerConfigGroup::merge(&mut config, NameServerConfigGroup::google())
@@ -407,8 +1209,9 @@ async fn fetch_resolve_config(command: &ArgMatches<'_>) -> NameServerConfigGroup
             .expect("Port expected")
             .parse::<u16>()
             .expect("Port expected to be a number");
+        let checks = name_server_checks(command, name_server_port, transport, rate).await;
         let ns_config = NameServerConfigGroup::from_ips_clear(
-            &validate_name_servers(nameservers).await,
+            &validate_name_servers(nameservers, checks.as_ref()).await,
             name_server_port,
         );
         NameServerConfigGroup::merge(&mut config, ns_config)
@@ -423,7 +1226,188 @@ async fn fetch_resolve_config(command: &ArgMatches<'_>) -> NameServerConfigGroup
     config
 }
 
-async fn validate_name_servers(ns_args: Values<'_>) -> Vec<IpAddr> {
+/// The name servers an axfr or dnssec query is sent to: those given with
+/// `-n`, otherwise the name servers of the domain found through the
+/// recursive `engine`.
+async fn operation_name_servers(
+    command: &ArgMatches<'_>,
+    domain: &str,
+    engine: &Engine,
+) -> Vec<resolver::NameServer> {
+    if let Some(ns_args) = command.values_of("NAMES_SERVERS") {
+        return labelled_name_servers(command, ns_args).await;
+    }
+    match resolver::discover_name_servers(domain, engine, DNS_PORT).await {
+        Ok(discovered) => {
+            progress::note(format!(
+                "Found the name servers of {}: {}",
+                domain,
+                discovered
+                    .iter()
+                    .map(|ns| ns.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            discovered
+        }
+        Err(e) => {
+            progress::note(format!("[{}] {}", "ERR".red(), e));
+            vec![]
+        }
+    }
+}
+
+/// The addresses of the name servers given, labelled with the name or
+/// address they were given as.
+async fn labelled_name_servers(
+    command: &ArgMatches<'_>,
+    ns_args: impl Iterator<Item = &str>,
+) -> Vec<resolver::NameServer> {
+    let port = command
+        .value_of("NAME_SERVER_PORT")
+        .expect("Port expected")
+        .parse::<u16>()
+        .expect("Port expected to be a number");
+    let system = AsyncResolver::tokio_from_system_conf()
+        .await
+        .expect("Error creating system config resolver");
+    let mut labelled = vec![];
+    for ns_arg in ns_args {
+        match validate_name_server(ns_arg, &system).await {
+            Ok(ips) => labelled.extend(ips.into_iter().map(|ip| resolver::NameServer {
+                host: ns_arg.to_string(),
+                addr: SocketAddr::new(ip, port),
+            })),
+            Err(e) => progress::note(format!("Error resolving name sever {}", e)),
+        }
+    }
+    labelled
+}
+
+/// The zone of `domain` a diff compares: transferred from the name
+/// servers of the domain for `axfr`, from the one named for
+/// `axfr:<name server>`, otherwise read from a file.
+async fn load_zone(
+    command: &ArgMatches<'_>,
+    domain: &str,
+    source: &str,
+    engine: &Engine,
+    concurrency: usize,
+    key: Option<&tsig::Key>,
+    transports: &[transport::Transport],
+) -> Result<diff::Zone, String> {
+    let origin = wildcard::to_fqdn(
+        &domain
+            .parse::<Name>()
+            .map_err(|e| format!("Invalid domain {}: {}", domain, e))?,
+    );
+    let ns = if source == "axfr" {
+        operation_name_servers(command, domain, engine).await
+    } else if let Some(name_server) = source.strip_prefix("axfr:") {
+        labelled_name_servers(command, std::iter::once(name_server)).await
+    } else {
+        return diff::Zone::from_file(&origin, source);
+    };
+    // Only the transfers are of interest, not the records as they arrive.
+    let (found, _) = mpsc::unbounded();
+    let transfers = transfer::transfer(domain, &ns, concurrency, key, transports, &found).await;
+    match transfers
+        .iter()
+        .find(|transfer| transfer.status == transfer::Status::Allowed)
+    {
+        Some(transfer) => {
+            progress::note(format!(
+                "Transferred the zone for {} from {}",
+                source, transfer.name_server
+            ));
+            diff::Zone::from_records(&origin, &transfer.records)
+        }
+        None => Err(format!(
+            "No name server allowed the transfer for {}: {}",
+            source,
+            transfers
+                .iter()
+                .map(|transfer| format!("{} {}", transfer.name_server, transfer.status))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// An engine querying the authoritative name servers of `domain`, found
+/// and followed through the recursive `name_servers`.
+async fn authoritative_engine(
+    domain: &str,
+    name_servers: &NameServerConfigGroup,
+    transport: &transport::Transport,
+    limits: Limits,
+    rate: Arc<RateController>,
+) -> Result<Engine, String> {
+    let domain = wildcard::to_fqdn(
+        &domain
+            .parse::<Name>()
+            .map_err(|e| format!("Invalid domain {}: {}", domain, e))?,
+    );
+    let recursive = Engine::recursive(name_servers, transport.clone(), limits, rate.clone());
+    let addrs = authoritative::zone_name_servers(&domain, &recursive, DNS_PORT).await?;
+    Engine::authoritative(
+        addrs,
+        transport.clone(),
+        authoritative::Delegations::new(recursive, DNS_PORT),
+        limits,
+        rate,
+    )
+    .await
+}
+
+/// The checks name servers given for a brute force have to pass.
+async fn name_server_checks(
+    command: &ArgMatches<'_>,
+    port: u16,
+    transport: &transport::Transport,
+    rate: Arc<RateController>,
+) -> Option<vet::Checks> {
+    if command.value_of("OPERATION") != Some("brute") || command.is_present("NO_NS_CHECKS") {
+        return None;
+    }
+    let domain = command
+        .value_of("DOMAIN")
+        .expect("domain expected")
+        .parse::<Name>()
+        .ok()?;
+    let trusted = match command.value_of("TRUSTED_NS") {
+        Some(ip) => {
+            let ip = ip.parse::<IpAddr>().expect("trusted name server expected");
+            NameServerConfigGroup::from_ips_clear(&[ip], DNS_PORT)
+        }
+        None => NameServerConfigGroup::from(
+            read_system_conf()
+                .expect("Error reading the system resolver config")
+                .0
+                .name_servers()
+                .to_vec(),
+        ),
+    };
+    Some(vet::Checks {
+        domain: wildcard::to_fqdn(&domain),
+        port,
+        known_answers: command
+            .values_of("KNOWN_ANSWERS")
+            .map(|values| {
+                values
+                    .map(|known| known.parse().expect("known answer expected"))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        transport: transport.clone(),
+        trusted: Engine::recursive(&trusted, transport.clone(), Limits::default(), rate),
+        keep_untrusted: command.is_present("KEEP_UNTRUSTED_NS"),
+    })
+}
+
+/// Resolve the name server arguments to addresses, dropping the name
+/// servers that fail the `checks` when given.
+async fn validate_name_servers(ns_args: Values<'_>, checks: Option<&vet::Checks>) -> Vec<IpAddr> {
     let resolver = AsyncResolver::tokio_from_system_conf()
         .await
         .expect("Error creating system config resolver");
@@ -435,12 +1419,11 @@ async fn validate_name_servers(ns_args: Values<'_>) -> Vec<IpAddr> {
     errors
         .into_iter()
         .map(Result::unwrap_err)
-        .for_each(|e| println!("Error resolving name sever {}", e));
-    let ips = ips
-        .into_iter()
-        .map(Result::unwrap)
-        .flatten()
-        .collect::<Vec<_>>();
+        .for_each(|e| progress::note(format!("Error resolving name sever {}", e)));
+    let mut ips = ips.into_iter().flat_map(Result::unwrap).collect::<Vec<_>>();
+    if let Some(checks) = checks {
+        ips = vet::vet_resolvers(ips, checks).await;
+    }
     if ips.is_empty() {
         panic!("No valid name servers found.")
     }
@@ -484,7 +1467,7 @@ mod tests {
     #[test]
     fn test_display_rdata_aname_rec() {
         let name = Name::from_str("localhost").unwrap();
-        let name_format = format!("{}", &name.to_utf8());
+        let name_format = name.to_utf8();
         assert_eq!(display_rdata(&RData::ANAME(name)), name_format);
     }
 
@@ -576,4 +1559,19 @@ mod tests {
         };
         assert_eq!(display_rdata(&unknown), "10 test");
     }
+
+    #[test]
+    fn test_validate_depth() {
+        assert!(validate_depth("3".to_string()).is_ok());
+        assert!(validate_depth("0".to_string()).is_err());
+        assert!(validate_depth("deep".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_validate_retries() {
+        assert!(validate_retries("0".to_string()).is_ok());
+        assert!(validate_retries("5".to_string()).is_ok());
+        assert!(validate_retries("-1".to_string()).is_err());
+        assert!(validate_retries("often".to_string()).is_err());
+    }
 }
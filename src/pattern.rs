@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// A candidate pattern such as `web[0-9][0-9]`, `{dev,stg,prod}-{api,db}`
+/// or `?l?l?d`.
+///
+/// * `[...]` matches one character from the class, ranges like `a-z` are allowed.
+/// * `{a,b}` matches one of the comma-separated alternatives.
+/// * `?l` a lowercase letter, `?d` a digit, `?h` a hex digit, `?a` a letter or digit.
+/// * `\` escapes the next character, anything else is literal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    segments: Vec<Vec<String>>,
+}
+
+impl Pattern {
+    /// The number of candidates the pattern expands to, saturating at `u128::MAX`.
+    pub fn keyspace(&self) -> u128 {
+        self.segments.iter().fold(1u128, |size, segment| {
+            size.saturating_mul(segment.len() as u128)
+        })
+    }
+
+    /// Lazily expand the pattern, the last segment varying fastest.
+    pub fn iter(&self) -> PatternIter<'_> {
+        PatternIter {
+            segments: &self.segments,
+            indices: vec![0; self.segments.len()],
+            done: self.segments.iter().any(Vec::is_empty),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Pattern, String> {
+        let mut segments = vec![];
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let segment = match c {
+                '[' => parse_class(&mut chars)?,
+                '{' => parse_alternatives(&mut chars)?,
+                '?' => match chars.next() {
+                    Some('l') => char_range('a', 'z'),
+                    Some('d') => char_range('0', '9'),
+                    Some('h') => char_range('0', '9')
+                        .into_iter()
+                        .chain(char_range('a', 'f'))
+                        .collect(),
+                    Some('a') => char_range('a', 'z')
+                        .into_iter()
+                        .chain(char_range('0', '9'))
+                        .collect(),
+                    Some('?') => vec!["?".to_string()],
+                    Some(m) => return Err(format!("Unknown mask ?{} in pattern", m)),
+                    None => return Err("Pattern ends with an incomplete mask".to_string()),
+                },
+                '\\' => match chars.next() {
+                    Some(escaped) => vec![escaped.to_string()],
+                    None => return Err("Pattern ends with an escape".to_string()),
+                },
+                ']' | '}' => return Err(format!("Unbalanced {} in pattern", c)),
+                c => vec![c.to_string()],
+            };
+            segments.push(segment);
+        }
+        if segments.is_empty() {
+            return Err("Pattern is empty".to_string());
+        }
+        Ok(Pattern { segments })
+    }
+}
+
+fn char_range(start: char, end: char) -> Vec<String> {
+    (start..=end).map(|c| c.to_string()).collect()
+}
+
+/// Parse a character class, the opening `[` has been consumed.
+fn parse_class(chars: &mut std::str::Chars) -> Result<Vec<String>, String> {
+    let mut members = vec![];
+    let mut previous: Option<char> = None;
+    loop {
+        match chars.next() {
+            Some(']') => break,
+            Some('-') if previous.is_some() && !chars.as_str().starts_with(']') => {
+                let start = previous.take().unwrap();
+                let end = match chars.next() {
+                    Some('\\') => chars.next(),
+                    end => end,
+                }
+                .ok_or("Unterminated [ in pattern")?;
+                if end < start {
+                    return Err(format!("Invalid range {}-{} in pattern", start, end));
+                }
+                members.pop();
+                members.extend(char_range(start, end));
+            }
+            Some('\\') => {
+                let escaped = chars.next().ok_or("Unterminated [ in pattern")?;
+                members.push(escaped.to_string());
+                previous = Some(escaped);
+            }
+            Some(c) => {
+                members.push(c.to_string());
+                previous = Some(c);
+            }
+            None => return Err("Unterminated [ in pattern".to_string()),
+        }
+    }
+    if members.is_empty() {
+        return Err("Empty [] in pattern".to_string());
+    }
+    // A class is a set, members named twice are only expanded once.
+    members.sort();
+    members.dedup();
+    Ok(members)
+}
+
+/// Parse a list of alternatives, the opening `{` has been consumed.
+fn parse_alternatives(chars: &mut std::str::Chars) -> Result<Vec<String>, String> {
+    let mut alternatives = vec![];
+    let mut current = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(',') => alternatives.push(std::mem::take(&mut current)),
+            Some('\\') => current.push(chars.next().ok_or("Unterminated { in pattern")?),
+            Some(c) => current.push(c),
+            None => return Err("Unterminated { in pattern".to_string()),
+        }
+    }
+    alternatives.push(current);
+    // Keep the first of each alternative, in the order given.
+    let mut seen = HashSet::new();
+    alternatives.retain(|alternative| seen.insert(alternative.clone()));
+    Ok(alternatives)
+}
+
+/// The lazy expansion of a [`Pattern`].
+pub struct PatternIter<'a> {
+    segments: &'a [Vec<String>],
+    indices: Vec<usize>,
+    done: bool,
+}
+
+impl<'a> Iterator for PatternIter<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.done {
+            return None;
+        }
+        let candidate = self
+            .segments
+            .iter()
+            .zip(self.indices.iter())
+            .map(|(segment, i)| segment[*i].as_str())
+            .collect::<String>();
+        self.done = true;
+        for (segment, i) in self.segments.iter().zip(self.indices.iter_mut()).rev() {
+            *i += 1;
+            if *i < segment.len() {
+                self.done = false;
+                break;
+            }
+            *i = 0;
+        }
+        Some(candidate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(pattern: &str) -> Vec<String> {
+        pattern.parse::<Pattern>().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_pattern_class() {
+        let candidates = expand("web[0-9][0-9]");
+        assert_eq!(candidates.len(), 100);
+        assert_eq!(candidates[0], "web00");
+        assert_eq!(candidates[11], "web11");
+        assert_eq!(candidates[99], "web99");
+    }
+
+    #[test]
+    fn test_pattern_alternatives() {
+        assert_eq!(
+            expand("{dev,stg}-{api,db}"),
+            vec!["dev-api", "dev-db", "stg-api", "stg-db"]
+        );
+    }
+
+    #[test]
+    fn test_pattern_masks() {
+        let pattern = "?l?l?d".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.keyspace(), 26 * 26 * 10);
+        assert_eq!(pattern.iter().next(), Some("aa0".to_string()));
+        assert_eq!(pattern.iter().last(), Some("zz9".to_string()));
+    }
+
+    #[test]
+    fn test_pattern_class_literal_dash() {
+        assert_eq!(expand("[a-c-]"), vec!["-", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_pattern_drops_duplicates() {
+        assert_eq!(expand("[aba]"), vec!["a", "b"]);
+        assert_eq!(expand("[a-ca-b]"), vec!["a", "b", "c"]);
+        assert_eq!(expand("{b,a,b}"), vec!["b", "a"]);
+        let pattern = "[aa]{x,x}".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.keyspace(), 1);
+    }
+
+    #[test]
+    fn test_pattern_escapes() {
+        assert_eq!(expand("a\\[b"), vec!["a[b"]);
+        assert_eq!(expand("{a\\,b,c}"), vec!["a,b", "c"]);
+    }
+
+    #[test]
+    fn test_pattern_keyspace_matches_expansion() {
+        let pattern = "{a,b,c}[x-z]?d".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.keyspace(), pattern.iter().count() as u128);
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        assert!("web[0-9".parse::<Pattern>().is_err());
+        assert!("{dev,stg".parse::<Pattern>().is_err());
+        assert!("?x".parse::<Pattern>().is_err());
+        assert!("[9-0]".parse::<Pattern>().is_err());
+        assert!("".parse::<Pattern>().is_err());
+        assert!("a]".parse::<Pattern>().is_err());
+    }
+}
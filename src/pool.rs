@@ -0,0 +1,326 @@
+use crate::progress;
+use crate::rate::{Outcome, RateController};
+use colored::*;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// The number of failures in a row after which a server is ejected.
+const EJECT_AFTER: usize = 25;
+
+/// The weight of the latest response in a server's latency average.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// How much a server's failure ratio inflates its latency score.
+const FAILURE_PENALTY: f64 = 10.0;
+
+/// The limits applied to every server of a pool.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// The queries per second sent to a single server.
+    pub rate: Option<usize>,
+    /// The queries in flight on a single server.
+    pub concurrency: Option<usize>,
+}
+
+/// The name servers queries are spread over.
+///
+/// Each server keeps its own rate and concurrency limits and a score made
+/// from its average latency and failure ratio.  Queries go to the better
+/// scoring of two randomly picked servers, so load follows health without
+/// piling onto a single server, and a server failing too many queries in
+/// a row is ejected from the pool for the rest of the run.
+pub struct ResolverPool {
+    servers: Vec<Server>,
+    /// Held while a server is ejected, so two servers failing at once can
+    /// not both be ejected as if the other were still healthy.
+    ejecting: Mutex<()>,
+}
+
+/// A name server of the pool.
+pub struct Server {
+    addr: SocketAddr,
+    rate: Option<RateController>,
+    slots: Option<Semaphore>,
+    stats: Mutex<Stats>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Stats {
+    queries: usize,
+    answers: usize,
+    errors: usize,
+    timeouts: usize,
+    latency: f64,
+    consecutive_failures: usize,
+    ejected: bool,
+}
+
+impl Stats {
+    fn failure_ratio(&self) -> f64 {
+        if self.queries == 0 {
+            0.0
+        } else {
+            (self.errors + self.timeouts) as f64 / self.queries as f64
+        }
+    }
+
+    /// Lower is better, servers that have not been tried yet score best.
+    fn score(&self) -> f64 {
+        self.latency * (1.0 + FAILURE_PENALTY * self.failure_ratio())
+    }
+}
+
+impl Server {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Wait until the server may take another query, the permit holds its
+    /// concurrency slot until dropped.
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permit = match &self.slots {
+            Some(slots) => Some(slots.acquire().await),
+            None => None,
+        };
+        if let Some(rate) = &self.rate {
+            rate.wait().await;
+        }
+        permit
+    }
+
+    fn stats(&self) -> std::sync::MutexGuard<'_, Stats> {
+        self.stats.lock().expect("server stats lock poisoned")
+    }
+}
+
+impl ResolverPool {
+    pub fn new(addrs: Vec<SocketAddr>, limits: Limits) -> ResolverPool {
+        let servers = addrs
+            .into_iter()
+            .map(|addr| Server {
+                addr,
+                rate: limits.rate.map(|rate| RateController::new(rate, rate)),
+                slots: limits.concurrency.map(Semaphore::new),
+                stats: Mutex::new(Stats::default()),
+            })
+            .collect();
+        ResolverPool {
+            servers,
+            ejecting: Mutex::new(()),
+        }
+    }
+
+    pub fn server(&self, index: usize) -> &Server {
+        &self.servers[index]
+    }
+
+    /// Pick the server for the next attempt of a query, avoiding the
+    /// servers in `tried` and ejected servers while others remain.
+    pub fn select(&self, tried: &[usize]) -> usize {
+        let healthy = (0..self.servers.len())
+            .filter(|i| !self.servers[*i].stats().ejected)
+            .collect::<Vec<_>>();
+        let untried = healthy
+            .iter()
+            .copied()
+            .filter(|i| !tried.contains(i))
+            .collect::<Vec<_>>();
+        let candidates = if !untried.is_empty() {
+            untried
+        } else if !healthy.is_empty() {
+            healthy
+        } else {
+            (0..self.servers.len()).collect()
+        };
+        candidates
+            .choose_multiple(&mut thread_rng(), 2)
+            .copied()
+            .min_by(|a, b| {
+                let a = self.servers[*a].stats().score();
+                let b = self.servers[*b].stats().score();
+                a.total_cmp(&b)
+            })
+            .expect("resolver pool is empty")
+    }
+
+    /// Count how a query to the server at `index` went, ejecting it once
+    /// it keeps failing, unless it is the last server left.
+    pub fn record(&self, index: usize, outcome: Outcome, latency: Duration) {
+        let server = &self.servers[index];
+        let failing = {
+            let mut stats = server.stats();
+            stats.queries += 1;
+            match outcome {
+                Outcome::Answer => {
+                    stats.answers += 1;
+                    stats.consecutive_failures = 0;
+                }
+                Outcome::Error => stats.errors += 1,
+                Outcome::Timeout => stats.timeouts += 1,
+            }
+            if outcome != Outcome::Answer {
+                stats.consecutive_failures += 1;
+            }
+            let latency = latency.as_secs_f64() * 1000.0;
+            stats.latency = if stats.queries == 1 {
+                latency
+            } else {
+                stats.latency * (1.0 - LATENCY_WEIGHT) + latency * LATENCY_WEIGHT
+            };
+            !stats.ejected && stats.consecutive_failures >= EJECT_AFTER
+        };
+        if failing {
+            self.eject(index);
+        }
+    }
+
+    /// Eject the server at `index` unless it is the last healthy one.
+    fn eject(&self, index: usize) {
+        let _ejecting = self.ejecting.lock().expect("resolver pool lock poisoned");
+        let healthy = self
+            .servers
+            .iter()
+            .filter(|server| !server.stats().ejected)
+            .count();
+        let server = &self.servers[index];
+        let mut stats = server.stats();
+        if stats.ejected || healthy <= 1 {
+            return;
+        }
+        stats.ejected = true;
+        progress::note(format!(
+            "[{}] Ejecting name server {} after {} failed queries in a row",
+            "WRN".yellow(),
+            server.addr,
+            stats.consecutive_failures
+        ));
+    }
+
+    /// A table of the queries each server handled.
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{:<40} {:>8} {:>8} {:>8} {:>8} {:>9}  {}",
+            "Name server", "Queries", "Answers", "Errors", "Timeouts", "Latency", "Status"
+        );
+        for server in &self.servers {
+            let stats = server.stats();
+            report.push_str(&format!(
+                "\n{:<40} {:>8} {:>8} {:>8} {:>8} {:>7.0}ms  {}",
+                server.addr.to_string(),
+                stats.queries,
+                stats.answers,
+                stats.errors,
+                stats.timeouts,
+                stats.latency,
+                if stats.ejected { "ejected" } else { "healthy" }
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(servers: usize) -> ResolverPool {
+        let addrs = (0..servers)
+            .map(|i| SocketAddr::from(([10, 0, 0, i as u8], 53)))
+            .collect();
+        ResolverPool::new(addrs, Limits::default())
+    }
+
+    fn record(pool: &ResolverPool, index: usize, outcome: Outcome, millis: u64, count: usize) {
+        for _ in 0..count {
+            pool.record(index, outcome, Duration::from_millis(millis));
+        }
+    }
+
+    #[test]
+    fn test_pool_ejects_failing_server() {
+        let pool = pool(2);
+        record(&pool, 0, Outcome::Timeout, 2000, EJECT_AFTER - 1);
+        assert!(!pool.server(0).stats().ejected);
+        record(&pool, 0, Outcome::Timeout, 2000, 1);
+        assert!(pool.server(0).stats().ejected);
+        for _ in 0..20 {
+            assert_eq!(pool.select(&[]), 1);
+        }
+    }
+
+    #[test]
+    fn test_pool_keeps_last_server() {
+        let pool = pool(2);
+        record(&pool, 0, Outcome::Error, 10, EJECT_AFTER);
+        record(&pool, 1, Outcome::Error, 10, EJECT_AFTER);
+        assert!(pool.server(0).stats().ejected);
+        assert!(!pool.server(1).stats().ejected);
+    }
+
+    #[test]
+    fn test_pool_keeps_last_server_under_concurrent_failures() {
+        for _ in 0..50 {
+            let pool = std::sync::Arc::new(pool(2));
+            let threads = (0..2)
+                .map(|index| {
+                    let pool = pool.clone();
+                    std::thread::spawn(move || {
+                        record(&pool, index, Outcome::Timeout, 10, EJECT_AFTER * 2)
+                    })
+                })
+                .collect::<Vec<_>>();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let ejected = (0..2).filter(|i| pool.server(*i).stats().ejected).count();
+            assert_eq!(ejected, 1);
+        }
+    }
+
+    #[test]
+    fn test_pool_answers_reset_failures() {
+        let pool = pool(2);
+        record(&pool, 0, Outcome::Timeout, 2000, EJECT_AFTER - 1);
+        record(&pool, 0, Outcome::Answer, 20, 1);
+        record(&pool, 0, Outcome::Timeout, 2000, EJECT_AFTER - 1);
+        assert!(!pool.server(0).stats().ejected);
+    }
+
+    #[test]
+    fn test_pool_prefers_healthy_servers() {
+        let pool = pool(2);
+        record(&pool, 0, Outcome::Answer, 100, 10);
+        record(&pool, 1, Outcome::Answer, 20, 10);
+        for _ in 0..20 {
+            assert_eq!(pool.select(&[]), 1);
+        }
+        record(&pool, 1, Outcome::Error, 20, 10);
+        assert_eq!(pool.select(&[]), 0);
+    }
+
+    #[test]
+    fn test_pool_avoids_tried_servers() {
+        let pool = pool(3);
+        for _ in 0..20 {
+            assert_eq!(pool.select(&[0, 2]), 1);
+        }
+        assert!(pool.select(&[0, 1, 2]) < 3);
+    }
+
+    #[test]
+    fn test_pool_report() {
+        let pool = pool(1);
+        record(&pool, 0, Outcome::Answer, 20, 3);
+        record(&pool, 0, Outcome::Timeout, 20, 1);
+        let report = pool.report();
+        let row = report.lines().nth(1).unwrap();
+        assert_eq!(
+            row.split_whitespace().collect::<Vec<_>>(),
+            vec!["10.0.0.0:53", "4", "3", "0", "1", "20ms", "healthy"]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::brute::{Counts, Tally};
+use crate::engine::Engine;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the status line is redrawn on a terminal.
+const TTY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a status line is printed when stderr is not a terminal.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The weight of the latest interval in the throughput average.
+const THROUGHPUT_WEIGHT: f64 = 0.3;
+
+/// Whether a status line is drawn on the terminal and has to be cleared
+/// before anything else is printed.
+static STATUS_LINE: AtomicBool = AtomicBool::new(false);
+
+/// Reports the progress of a run on stderr, keeping stdout for results.
+///
+/// On a terminal a single status line is redrawn in place, otherwise a
+/// plain status line is printed every few seconds.
+pub struct Progress {
+    done: Arc<AtomicBool>,
+}
+
+impl Progress {
+    pub fn start(tally: Arc<Tally>, engine: Engine) -> Progress {
+        let tty = std::io::stderr().is_terminal();
+        let done = Arc::new(AtomicBool::new(false));
+        tokio::spawn(report(tally, engine, tty, done.clone()));
+        Progress { done }
+    }
+
+    /// Clear the status line so a result can be printed in its place.
+    pub fn clear(&self) {
+        clear_status_line();
+    }
+
+    /// Stop reporting and remove the status line.
+    pub fn finish(&self) {
+        self.clear();
+        self.done.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Print a diagnostic on stderr, in place of the status line if one is
+/// drawn, it is redrawn below on the next update.
+pub fn note(message: impl Display) {
+    clear_status_line();
+    eprintln!("{}", message);
+}
+
+fn clear_status_line() {
+    if STATUS_LINE.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+async fn report(tally: Arc<Tally>, engine: Engine, tty: bool, done: Arc<AtomicBool>) {
+    let interval = if tty { TTY_INTERVAL } else { PLAIN_INTERVAL };
+    let start = Instant::now();
+    let mut last = (start, 0);
+    let mut throughput = None;
+    loop {
+        tokio::time::delay_for(interval).await;
+        if done.load(Ordering::Relaxed) {
+            return;
+        }
+        let counts = tally.counts();
+        let now = Instant::now();
+        let latest = (counts.done() - last.1) as f64 / now.duration_since(last.0).as_secs_f64();
+        let average = match throughput {
+            Some(average) => average * (1.0 - THROUGHPUT_WEIGHT) + latest * THROUGHPUT_WEIGHT,
+            None => latest,
+        };
+        throughput = Some(average);
+        last = (now, counts.done());
+        let line = status(&counts, average, engine.rate(), now.duration_since(start));
+        if tty {
+            eprint!("\r\x1b[K{}", line);
+            STATUS_LINE.store(true, Ordering::Relaxed);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+/// The status line for the counts after `elapsed`.
+fn status(counts: &Counts, throughput: f64, rate: f64, elapsed: Duration) -> String {
+    let done = counts.done();
+    let mut parts = vec![];
+    match counts.queued {
+        Some(queued) if queued > 0 => parts.push(format!(
+            "{}/{} ({:.0}%)",
+            done,
+            queued,
+            (done as f64 * 100.0 / queued as f64).min(100.0)
+        )),
+        _ => parts.push(format!("{} done", done)),
+    }
+    parts.push(format!(
+        "{:.0} candidates/s at {:.0} queries/s",
+        throughput, rate
+    ));
+    parts.push(format!("{} found", counts.found));
+    let classes = [
+        ("wildcard", counts.wildcard),
+        ("NXDOMAIN", counts.nxdomain),
+        ("NODATA", counts.nodata),
+        ("SERVFAIL", counts.servfail),
+        ("REFUSED", counts.refused),
+        ("timeout", counts.timeout),
+        ("failed", counts.failed),
+    ];
+    let classes = classes
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(class, count)| format!("{} {}", class, count))
+        .collect::<Vec<_>>();
+    if !classes.is_empty() {
+        parts.push(classes.join(" "));
+    }
+    parts.push(format!("ETA {}", eta(counts, elapsed)));
+    parts.join(" | ")
+}
+
+/// The time left at the average pace since the start, `--` until it can
+/// be told.
+fn eta(counts: &Counts, elapsed: Duration) -> String {
+    let done = counts.done() as f64;
+    match counts.queued {
+        Some(queued) if done > 0.0 => {
+            let remaining = (queued as f64 - done).max(0.0);
+            let seconds = remaining * elapsed.as_secs_f64() / done;
+            format_duration(Duration::from_secs_f64(seconds))
+        }
+        _ => "--".to_string(),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(7)), "7s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m05s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+
+    #[test]
+    fn test_status() {
+        let counts = Counts {
+            queued: Some(1000),
+            found: 10,
+            nxdomain: 230,
+            timeout: 10,
+            ..Counts::default()
+        };
+        assert_eq!(
+            status(&counts, 49.6, 100.0, Duration::from_secs(5)),
+            "250/1000 (25%) | 50 candidates/s at 100 queries/s | 10 found | NXDOMAIN 230 timeout 10 | ETA 15s"
+        );
+    }
+
+    #[test]
+    fn test_status_unknown_total() {
+        let counts = Counts {
+            queued: None,
+            found: 1,
+            ..Counts::default()
+        };
+        assert_eq!(
+            status(&counts, 1.0, 10.0, Duration::from_secs(1)),
+            "1 done | 1 candidates/s at 10 queries/s | 1 found | ETA --"
+        );
+    }
+}
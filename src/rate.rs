@@ -0,0 +1,226 @@
+use crate::progress;
+use colored::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the rate is reconsidered.
+const ADJUST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of responses a resolver needs in a window before its
+/// failure ratio is trusted.
+const MIN_SAMPLES: usize = 10;
+
+/// The ratio of errors and timeouts above which a resolver is unhealthy.
+const FAILURE_THRESHOLD: f64 = 0.05;
+
+/// The factor the rate is cut by when a resolver is unhealthy.
+const DECREASE: f64 = 0.5;
+
+/// The fraction of the maximum rate added back each healthy window.
+const INCREASE: f64 = 0.1;
+
+/// How a query to a resolver ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Any answer, including NXDOMAIN and empty answers.
+    Answer,
+    /// SERVFAIL, REFUSED or a failure to talk to the resolver.
+    Error,
+    /// No response in time.
+    Timeout,
+}
+
+/// The responses counted for a resolver since the last adjustment.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Window {
+    answers: usize,
+    errors: usize,
+    timeouts: usize,
+}
+
+impl Window {
+    fn count(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Answer => self.answers += 1,
+            Outcome::Error => self.errors += 1,
+            Outcome::Timeout => self.timeouts += 1,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.answers + self.errors + self.timeouts
+    }
+
+    fn failure_ratio(&self) -> f64 {
+        (self.errors + self.timeouts) as f64 / self.total() as f64
+    }
+}
+
+/// Paces queries and adapts their rate to how the resolvers cope.
+///
+/// The controller is AIMD: the error and timeout ratio of every resolver
+/// is checked each second, the rate is halved as soon as one of them
+/// fails too many queries and is raised by a tenth of the maximum when
+/// they are all healthy, never leaving `min_rate..=max_rate`.
+pub struct RateController {
+    state: Mutex<State>,
+}
+
+struct State {
+    rate: f64,
+    min_rate: f64,
+    max_rate: f64,
+    next_slot: Instant,
+    last_adjust: Instant,
+    windows: HashMap<String, Window>,
+}
+
+impl RateController {
+    /// Start at `max_rate` queries per second.
+    pub fn new(max_rate: usize, min_rate: usize) -> RateController {
+        let max_rate = max_rate.max(1) as f64;
+        let now = Instant::now();
+        RateController {
+            state: Mutex::new(State {
+                rate: max_rate,
+                min_rate: (min_rate.max(1) as f64).min(max_rate),
+                max_rate,
+                next_slot: now,
+                last_adjust: now,
+                windows: HashMap::new(),
+            }),
+        }
+    }
+
+    /// The current rate in queries per second.
+    pub fn rate(&self) -> f64 {
+        self.state.lock().expect("rate lock poisoned").rate
+    }
+
+    /// Wait for the next query slot at the current rate.
+    pub async fn wait(&self) {
+        let slot = {
+            let mut state = self.state.lock().expect("rate lock poisoned");
+            let slot = state.next_slot.max(Instant::now());
+            state.next_slot = slot + Duration::from_secs_f64(1.0 / state.rate);
+            slot
+        };
+        tokio::time::delay_until(slot.into()).await;
+    }
+
+    /// Count the outcome of a query sent to `resolver`, adjusting the rate
+    /// once the current window is over.
+    pub fn record(&self, resolver: &str, outcome: Outcome) {
+        let mut state = self.state.lock().expect("rate lock poisoned");
+        state
+            .windows
+            .entry(resolver.to_string())
+            .or_default()
+            .count(outcome);
+        let now = Instant::now();
+        if now.duration_since(state.last_adjust) >= ADJUST_INTERVAL {
+            state.last_adjust = now;
+            if let Some(change) = state.adjust() {
+                progress::note(change);
+            }
+        }
+    }
+}
+
+impl State {
+    /// Apply one AIMD step from the counted windows and start new ones,
+    /// describing the change when the rate moved.
+    fn adjust(&mut self) -> Option<String> {
+        let windows = std::mem::take(&mut self.windows);
+        let worst = windows
+            .iter()
+            .filter(|(_, window)| window.total() >= MIN_SAMPLES)
+            .max_by(|(_, a), (_, b)| a.failure_ratio().total_cmp(&b.failure_ratio()))?;
+        let previous = self.rate;
+        let (resolver, window) = worst;
+        if window.failure_ratio() > FAILURE_THRESHOLD {
+            self.rate = (self.rate * DECREASE).max(self.min_rate);
+            if self.rate < previous {
+                return Some(format!(
+                    "[{}] Lowering the rate to {:.0} queries/s, {} failed {:.0}% of queries ({} errors, {} timeouts)",
+                    "WRN".yellow(),
+                    self.rate,
+                    resolver,
+                    window.failure_ratio() * 100.0,
+                    window.errors,
+                    window.timeouts
+                ));
+            }
+        } else {
+            self.rate = (self.rate + self.max_rate * INCREASE).min(self.max_rate);
+            if self.rate > previous {
+                return Some(format!("Raising the rate to {:.0} queries/s", self.rate));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(controller: &RateController, resolver: &str, outcome: Outcome, count: usize) {
+        let mut state = controller.state.lock().unwrap();
+        let window = state.windows.entry(resolver.to_string()).or_default();
+        for _ in 0..count {
+            window.count(outcome);
+        }
+    }
+
+    fn adjust(controller: &RateController) -> Option<String> {
+        controller.state.lock().unwrap().adjust()
+    }
+
+    #[test]
+    fn test_rate_decreases_on_failures() {
+        let controller = RateController::new(100, 10);
+        record(&controller, "1.1.1.1:53", Outcome::Answer, 50);
+        record(&controller, "8.8.8.8:53", Outcome::Answer, 40);
+        record(&controller, "8.8.8.8:53", Outcome::Timeout, 10);
+        assert!(adjust(&controller)
+            .unwrap()
+            .contains("8.8.8.8:53 failed 20%"));
+        assert_eq!(controller.rate(), 50.0);
+    }
+
+    #[test]
+    fn test_rate_increases_when_healthy() {
+        let controller = RateController::new(100, 10);
+        controller.state.lock().unwrap().rate = 50.0;
+        record(&controller, "1.1.1.1:53", Outcome::Answer, 50);
+        assert!(adjust(&controller).is_some());
+        assert_eq!(controller.rate(), 60.0);
+    }
+
+    #[test]
+    fn test_rate_stays_within_bounds() {
+        let controller = RateController::new(100, 30);
+        for _ in 0..5 {
+            record(&controller, "1.1.1.1:53", Outcome::Error, 20);
+            adjust(&controller);
+        }
+        assert_eq!(controller.rate(), 30.0);
+        for _ in 0..20 {
+            record(&controller, "1.1.1.1:53", Outcome::Answer, 20);
+            adjust(&controller);
+        }
+        assert_eq!(controller.rate(), 100.0);
+        record(&controller, "1.1.1.1:53", Outcome::Answer, 20);
+        assert_eq!(adjust(&controller), None);
+    }
+
+    #[test]
+    fn test_rate_ignores_small_windows() {
+        let controller = RateController::new(100, 10);
+        record(&controller, "1.1.1.1:53", Outcome::Timeout, MIN_SAMPLES - 1);
+        assert_eq!(adjust(&controller), None);
+        assert_eq!(controller.rate(), 100.0);
+    }
+}
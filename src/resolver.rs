@@ -1,109 +1,204 @@
+use crate::authoritative;
+use crate::engine::Engine;
+use crate::progress;
+use crate::transport::{request, Transport};
+use colored::*;
 use futures::prelude::*;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
-use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::net::UdpSocket;
-use trust_dns_client::client::AsyncClient;
-use trust_dns_client::rr::dnssec::Signer;
-use trust_dns_client::rr::{DNSClass, Name, Record, RecordType};
-use trust_dns_client::tcp::TcpClientStream;
-use trust_dns_client::udp::UdpClientStream;
-use trust_dns_proto::xfer::DnsMultiplexer;
-use trust_dns_proto::{iocompat::AsyncIo02As03, TokioTime};
+use trust_dns_client::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::op::Query;
 
-use trust_dns_client::client::*;
+/// A name server an operation runs against, labelled with the host name
+/// it was found under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameServer {
+    pub host: String,
+    pub addr: SocketAddr,
+}
 
-pub async fn query(
+impl From<SocketAddr> for NameServer {
+    /// A name server known by its address alone.
+    fn from(addr: SocketAddr) -> NameServer {
+        NameServer {
+            host: addr.ip().to_string(),
+            addr,
+        }
+    }
+}
+
+impl std::fmt::Display for NameServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host == self.addr.ip().to_string() {
+            write!(f, "{}", self.addr.ip())
+        } else {
+            write!(f, "{}/{}", self.host, self.addr.ip())
+        }
+    }
+}
+
+/// Find the name servers of `domain` through the recursive `engine`, with
+/// every IPv4 and IPv6 address of each one.
+pub async fn discover_name_servers(
     domain: &str,
-    name_servers: &[IpAddr],
+    engine: &Engine,
     port: u16,
-    concurrency: usize,
-    record_type: RecordType,
-) -> Vec<Record> {
-    let (records, errors): (Vec<_>, Vec<_>) = stream::iter(name_servers)
-        .then(|address| {
-            let socket = SocketAddr::new(*address, port);
-            let (stream, sender) =
-                TcpClientStream::<AsyncIo02As03<TcpStream>>::new::<TokioTime>(socket);
-            let mp = DnsMultiplexer::new(stream, sender, None::<Arc<Signer>>);
-            AsyncClient::connect(mp).map_err(move |_| {
-                format!("Failed to create client for address: {}:{}", address, port)
-            })
-        })
-        .and_then(|(client, bg)| {
-            tokio::spawn(bg);
-            async {
-                Name::from_str(domain)
-                    .map(|name| (client, name, domain))
-                    .map_err(|_| format!("Failed to create name: {}", domain))
+) -> Result<Vec<NameServer>, String> {
+    let zone = Name::from_str(domain).map_err(|e| format!("Invalid domain {}: {}", domain, e))?;
+    let mut name_servers = vec![];
+    for host in authoritative::ns_hosts(&zone, engine).await? {
+        let mut ips = vec![];
+        for record_type in [RecordType::A, RecordType::AAAA].iter() {
+            if let Ok(lookup) = engine.lookup(host.clone(), *record_type).await {
+                ips.extend(
+                    lookup
+                        .record_iter()
+                        .filter_map(|record| match record.rdata() {
+                            RData::A(ip) => Some(IpAddr::V4(*ip)),
+                            RData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+                            _ => None,
+                        }),
+                );
             }
-        })
-        .map_ok(|(mut client, name, domain)| {
-            client
-                .query(name, DNSClass::IN, record_type)
-                .map_err(move |_| format!("{} query failed: {}", record_type, domain))
-        })
-        .try_buffer_unordered(concurrency)
-        .map_ok(|response| response.answers().to_vec())
-        .collect::<Vec<_>>()
-        .await
-        .into_iter()
-        .partition(Result::is_ok);
-    let records = records
-        .into_iter()
-        .map(|x| x.unwrap())
-        .flatten()
-        .collect::<Vec<_>>();
-    errors
-        .into_iter()
-        .map(|x| x.unwrap_err())
-        .for_each(|e| println!("{:?}", e));
-    records
+        }
+        if ips.is_empty() {
+            progress::note(format!(
+                "[{}] No address found for name server {}",
+                "WRN".yellow(),
+                host.to_ascii()
+            ));
+        }
+        name_servers.extend(ips.into_iter().map(|ip| NameServer {
+            host: host.to_ascii(),
+            addr: SocketAddr::new(ip, port),
+        }));
+    }
+    Ok(name_servers)
 }
 
-pub async fn query_udp(
+/// Query each name server for the `record_type` records of `domain`
+/// through `transport`, labelling the records with the name server that
+/// gave them.
+///
+/// Up to `concurrency` name servers are queried at once, and those that
+/// fail or do not answer in time are reported and left out.
+pub async fn query(
     domain: &str,
-    name_servers: &[IpAddr],
-    port: u16,
+    name_servers: &[NameServer],
     concurrency: usize,
     record_type: RecordType,
-) -> Vec<Record> {
+    transport: &Transport,
+) -> Vec<(NameServer, Record)> {
+    let name = match Name::from_str(domain) {
+        Ok(name) => name,
+        Err(_) => {
+            progress::note(format!("Failed to create name: {}", domain));
+            return vec![];
+        }
+    };
     let (records, errors): (Vec<_>, Vec<_>) = stream::iter(name_servers)
-        .then(|address| {
-            let socket = SocketAddr::new(*address, port);
-            let stream = UdpClientStream::<UdpSocket>::new(socket);
-            AsyncClient::connect(stream).map_err(move |_| {
-                format!("Failed to create client for address: {}:{}", address, port)
-            })
-        })
-        .and_then(|(client, bg)| {
-            tokio::spawn(bg);
-            async {
-                Name::from_str(domain)
-                    .map(|name| (client, name, domain))
-                    .map_err(|_| format!("Failed to create name: {}", domain))
-            }
-        })
-        .map_ok(|(mut client, name, domain)| {
-            client
-                .query(name, DNSClass::IN, record_type)
-                .map_err(move |_| format!("{} query failed: {}", record_type, domain))
-        })
-        .try_buffer_unordered(concurrency)
-        .map_ok(|response| response.answers().to_vec())
+        .map(|name_server| query_from(name.clone(), name_server, record_type, transport))
+        .buffer_unordered(concurrency)
         .collect::<Vec<_>>()
         .await
         .into_iter()
         .partition(Result::is_ok);
-    let records = records
-        .into_iter()
-        .map(|x| x.unwrap())
-        .flatten()
-        .collect::<Vec<_>>();
     errors
         .into_iter()
-        .map(|x| x.unwrap_err())
-        .for_each(|e| println!("{:?}", e));
-    records
+        .map(Result::unwrap_err)
+        .for_each(|e| progress::note(format!("[{}] {}", "ERR".red(), e)));
+    records.into_iter().flat_map(Result::unwrap).collect()
+}
+
+async fn query_from(
+    name: Name,
+    name_server: &NameServer,
+    record_type: RecordType,
+    transport: &Transport,
+) -> Result<Vec<(NameServer, Record)>, String> {
+    let request = request(Query::query(name.clone(), record_type), true);
+    let response = transport
+        .query(name_server, request, None)
+        .await
+        .map_err(|e| {
+            format!(
+                "{}: {} query for {} over {} failed: {}",
+                name_server, record_type, name, transport.policy, e
+            )
+        })?;
+    Ok(response
+        .answers()
+        .iter()
+        .map(|record| (name_server.clone(), record.clone()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{Policy, Timeouts};
+    use std::time::Duration;
+    use trust_dns_proto::op::{Message, MessageType};
+
+    /// Answer every A query over UDP with `ip`, on a thread of its own.
+    fn stand_in_server(ip: &str) -> SocketAddr {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let ip = ip.parse().unwrap();
+        std::thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            let request = Message::from_vec(&buf[..len]).unwrap();
+            let query = request.queries()[0].clone();
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .add_answer(Record::from_rdata(query.name().clone(), 60, RData::A(ip)))
+                .add_query(query);
+            socket.send_to(&response.to_vec().unwrap(), from).unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_query_leaves_out_silent_name_servers() {
+        // Bound, so queries to it are not refused, but never read.
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let name_servers = [
+            NameServer {
+                host: "silent".to_string(),
+                addr: silent.local_addr().unwrap(),
+            },
+            NameServer {
+                host: "answering".to_string(),
+                addr: stand_in_server("10.0.0.1"),
+            },
+        ];
+        let mut transport = Transport::new(Policy::Udp);
+        transport.timeouts = Timeouts {
+            connect: Duration::from_secs(1),
+            query: Duration::from_millis(500),
+        };
+        let started = std::time::Instant::now();
+        let records = query("example.com.", &name_servers, 2, RecordType::A, &transport).await;
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0.host, "answering");
+        assert_eq!(records[0].1.rdata(), &RData::A("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_name_server_display() {
+        let ns = NameServer {
+            host: "ns1.example.com.".to_string(),
+            addr: "192.0.2.1:53".parse().unwrap(),
+        };
+        assert_eq!(ns.to_string(), "ns1.example.com./192.0.2.1");
+        let ip = NameServer {
+            host: "2001:db8::1".to_string(),
+            addr: "[2001:db8::1]:53".parse().unwrap(),
+        };
+        assert_eq!(ip.to_string(), "2001:db8::1");
+    }
 }
@@ -0,0 +1,393 @@
+use crate::engine::{Engine, LookupError};
+use crate::wildcard::to_fqdn;
+use futures::prelude::*;
+use futures::stream;
+use std::str::FromStr;
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+/// The fingerprints bundled with the binary.
+const BUNDLED: &str = include_str!("fingerprints.txt");
+
+/// The most CNAMEs followed from a name before giving up.
+const MAX_CHAIN: usize = 10;
+
+/// A service a dangling CNAME can be taken over on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    provider: String,
+    /// The names CNAME targets of the service fall under.
+    suffixes: Vec<Name>,
+    /// Whether a target that does not exist can be registered with the
+    /// service by anyone.
+    nxdomain: bool,
+    /// What the service serves over HTTP for a resource nobody claimed.
+    http: Option<String>,
+}
+
+/// The services checked for takeovers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprints(Vec<Fingerprint>);
+
+impl FromStr for Fingerprints {
+    type Err = String;
+
+    /// Parse one `provider | suffixes | yes/no | http fingerprint` line per
+    /// service, skipping blank lines and `#` comments.
+    fn from_str(fingerprints: &str) -> Result<Fingerprints, String> {
+        fingerprints
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(i, line)| parse_fingerprint(line).map_err(|e| format!("Line {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()
+            .map(Fingerprints)
+    }
+}
+
+fn parse_fingerprint(line: &str) -> Result<Fingerprint, String> {
+    let fields = line.split('|').map(str::trim).collect::<Vec<_>>();
+    if fields.len() < 3 || fields.len() > 4 {
+        return Err(format!(
+            "Fingerprint must be provider | suffixes | yes/no | http fingerprint {}",
+            line
+        ));
+    }
+    let suffixes = fields[1]
+        .split(',')
+        .map(|suffix| {
+            Name::from_str(suffix.trim())
+                .map(|name| to_fqdn(&name))
+                .map_err(|e| format!("Invalid suffix {}: {}", suffix, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let nxdomain = match fields[2] {
+        "yes" => true,
+        "no" => false,
+        other => return Err(format!("Expected yes or no, found {}", other)),
+    };
+    Ok(Fingerprint {
+        provider: fields[0].to_string(),
+        suffixes,
+        nxdomain,
+        http: fields
+            .get(3)
+            .filter(|http| !http.is_empty())
+            .map(|http| http.to_string()),
+    })
+}
+
+impl Fingerprints {
+    pub fn bundled() -> Fingerprints {
+        BUNDLED.parse().expect("invalid bundled fingerprints")
+    }
+
+    /// Read the fingerprints from a file, replacing the bundled ones.
+    pub fn from_file(path: &str) -> Result<Fingerprints, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read the fingerprints {}: {}", path, e))?
+            .parse()
+            .map_err(|e| format!("Invalid fingerprints {}: {}", path, e))
+    }
+
+    /// The service the name is hosted on.
+    fn matching(&self, name: &Name) -> Option<&Fingerprint> {
+        self.0.iter().find(|fingerprint| {
+            fingerprint
+                .suffixes
+                .iter()
+                .any(|suffix| suffix.zone_of(name))
+        })
+    }
+}
+
+/// How likely a finding is to be a takeover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Confidence::High => write!(f, "high"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::Low => write!(f, "low"),
+        }
+    }
+}
+
+/// Where a CNAME chain ended.
+#[derive(Debug, Clone, PartialEq)]
+enum End {
+    /// The last target does not exist.
+    NxDomain,
+    /// The last target exists.
+    Exists,
+    /// The chain could not be followed to its end.
+    Unknown(String),
+}
+
+/// A name that looks open to a takeover.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub name: Name,
+    /// The CNAME targets from the name to the end of the chain.
+    pub chain: Vec<Name>,
+    pub provider: String,
+    pub confidence: Confidence,
+    pub reason: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}:{}",
+            self.name.to_ascii(),
+            self.provider,
+            self.confidence,
+            self.chain
+                .iter()
+                .map(|name| name.to_ascii())
+                .collect::<Vec<_>>()
+                .join(" -> "),
+            self.reason
+        )
+    }
+}
+
+/// Check the CNAMEs among the records for takeovers.
+///
+/// The chain of every CNAME is followed to its end with CNAME queries,
+/// so the engine has to tell NXDOMAIN from NODATA.  A chain running
+/// through a known service is a finding when its end does not exist, or
+/// when the service has to be checked over HTTP.  A chain ending in a
+/// name that does not exist on an unknown service is reported with low
+/// confidence, the domain itself may be free to register.
+pub async fn check_takeovers(
+    records: &[Record],
+    engine: &Engine,
+    fingerprints: &Fingerprints,
+    concurrency: usize,
+) -> Vec<Finding> {
+    let mut cnames = records
+        .iter()
+        .filter_map(|record| match record.rdata() {
+            RData::CNAME(target) => Some((to_fqdn(record.name()), to_fqdn(target))),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    cnames.sort();
+    cnames.dedup_by(|a, b| a.0 == b.0);
+    let mut findings = stream::iter(cnames)
+        .map(|(name, target)| async move {
+            let (chain, end) = follow_chain(target, engine).await;
+            assess(name, chain, &end, fingerprints)
+        })
+        .buffer_unordered(concurrency)
+        .filter_map(future::ready)
+        .collect::<Vec<_>>()
+        .await;
+    findings.sort_by(|a, b| (a.confidence, &a.name).cmp(&(b.confidence, &b.name)));
+    findings
+}
+
+/// Follow the CNAMEs from `target` until a name without one.
+async fn follow_chain(target: Name, engine: &Engine) -> (Vec<Name>, End) {
+    let mut chain = vec![target];
+    loop {
+        let name = chain.last().expect("chain is never empty").clone();
+        let next = match engine.lookup(name.clone(), RecordType::CNAME).await {
+            Ok(lookup) => lookup
+                .record_iter()
+                .find_map(|record| match record.rdata() {
+                    RData::CNAME(next) if to_fqdn(record.name()) == name => Some(to_fqdn(next)),
+                    _ => None,
+                }),
+            Err(LookupError::NxDomain) => return (chain, End::NxDomain),
+            Err(LookupError::NoData) => None,
+            Err(e) => return (chain, End::Unknown(e.to_string())),
+        };
+        match next {
+            None => return (chain, End::Exists),
+            Some(next) if chain.contains(&next) => {
+                return (chain, End::Unknown("CNAME loop".to_string()))
+            }
+            Some(_) if chain.len() >= MAX_CHAIN => {
+                return (chain, End::Unknown("CNAME chain too long".to_string()))
+            }
+            Some(next) => chain.push(next),
+        }
+    }
+}
+
+/// Judge the chain of `name` against the fingerprints.
+fn assess(name: Name, chain: Vec<Name>, end: &End, fingerprints: &Fingerprints) -> Option<Finding> {
+    let last = chain.last().expect("chain is never empty").to_ascii();
+    let fingerprint = chain.iter().find_map(|link| fingerprints.matching(link));
+    let (provider, confidence, reason) = match (fingerprint, end) {
+        (Some(fingerprint), End::NxDomain) if fingerprint.nxdomain => (
+            fingerprint.provider.clone(),
+            Confidence::High,
+            format!("{} is NXDOMAIN and can be registered", last),
+        ),
+        (Some(fingerprint), End::NxDomain) => (
+            fingerprint.provider.clone(),
+            Confidence::Medium,
+            format!("{} is NXDOMAIN", last),
+        ),
+        (Some(fingerprint), _) => match &fingerprint.http {
+            Some(http) => (
+                fingerprint.provider.clone(),
+                Confidence::Low,
+                format!("check {} over HTTP for \"{}\"", name.to_ascii(), http),
+            ),
+            None => return None,
+        },
+        (None, End::NxDomain) => (
+            "unknown".to_string(),
+            Confidence::Low,
+            format!("{} is NXDOMAIN", last),
+        ),
+        (None, _) => return None,
+    };
+    let reason = match end {
+        End::Unknown(e) => format!("{}, the chain ends in {}", reason, e),
+        _ => reason,
+    };
+    Some(Finding {
+        name,
+        chain,
+        provider,
+        confidence,
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::transport;
+    use crate::pool::Limits;
+    use crate::rate::RateController;
+    use crate::udp_engine::tests::stand_in_server;
+    use std::sync::Arc;
+    use trust_dns_resolver::config::NameServerConfigGroup;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn cname(from: &str, to: &str) -> Record {
+        Record::from_rdata(name(from), 60, RData::CNAME(name(to)))
+    }
+
+    #[test]
+    fn test_bundled_fingerprints() {
+        let fingerprints = Fingerprints::bundled();
+        let azure = fingerprints
+            .matching(&name("app.azurewebsites.net."))
+            .unwrap();
+        assert_eq!(azure.provider, "Microsoft Azure");
+        assert!(azure.nxdomain);
+        let github = fingerprints.matching(&name("ORG.GitHub.io.")).unwrap();
+        assert_eq!(
+            github.http.as_deref(),
+            Some("There isn't a GitHub Pages site here.")
+        );
+        assert_eq!(fingerprints.matching(&name("notgithub.io.")), None);
+    }
+
+    #[test]
+    fn test_fingerprints_from_str() {
+        let fingerprints = "# comment\n\nExample | example.net,example.org | yes |\n"
+            .parse::<Fingerprints>()
+            .unwrap();
+        assert_eq!(fingerprints.0.len(), 1);
+        assert_eq!(fingerprints.0[0].suffixes.len(), 2);
+        assert_eq!(fingerprints.0[0].http, None);
+        assert!("Example | example.net | maybe"
+            .parse::<Fingerprints>()
+            .unwrap_err()
+            .starts_with("Line 1:"));
+        assert!("Example".parse::<Fingerprints>().is_err());
+    }
+
+    #[test]
+    fn test_assess() {
+        let fingerprints = Fingerprints::bundled();
+        let chain = vec![name("app.azurewebsites.net."), name("nx.cloudapp.net.")];
+        let high = assess(
+            name("www.example.com."),
+            chain.clone(),
+            &End::NxDomain,
+            &fingerprints,
+        )
+        .unwrap();
+        assert_eq!(high.provider, "Microsoft Azure");
+        assert_eq!(high.confidence, Confidence::High);
+        assert_eq!(
+            high.to_string(),
+            "www.example.com.:Microsoft Azure:high:app.azurewebsites.net. -> nx.cloudapp.net.:nx.cloudapp.net. is NXDOMAIN and can be registered"
+        );
+        assert_eq!(
+            assess(name("www.example.com."), chain, &End::Exists, &fingerprints),
+            None
+        );
+        let heroku = assess(
+            name("www.example.com."),
+            vec![name("app.herokuapp.com.")],
+            &End::NxDomain,
+            &fingerprints,
+        )
+        .unwrap();
+        assert_eq!(heroku.confidence, Confidence::Medium);
+        let unknown = assess(
+            name("www.example.com."),
+            vec![name("cdn.example.net.")],
+            &End::NxDomain,
+            &fingerprints,
+        )
+        .unwrap();
+        assert_eq!(
+            (unknown.provider.as_str(), unknown.confidence),
+            ("unknown", Confidence::Low)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_takeovers() {
+        // The stand-in points names starting with to- at their parent and
+        // has no names starting with nx.
+        let server = stand_in_server().await;
+        let name_servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port());
+        let rate = Arc::new(RateController::new(1_000_000, 1_000_000));
+        let engine = Engine::recursive(&name_servers, transport(), Limits::default(), rate);
+        let records = vec![
+            cname("a.example.com.", "to-x.nx-app.azurewebsites.net."),
+            cname("b.example.com.", "org.github.io."),
+            cname("c.example.com.", "www.example.net."),
+            Record::from_rdata(
+                name("d.example.com."),
+                60,
+                RData::A("10.0.0.1".parse().unwrap()),
+            ),
+        ];
+        let findings = check_takeovers(&records, &engine, &Fingerprints::bundled(), 10).await;
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].name, name("a.example.com."));
+        assert_eq!(findings[0].confidence, Confidence::High);
+        assert_eq!(
+            findings[0].chain,
+            vec![
+                name("to-x.nx-app.azurewebsites.net."),
+                name("nx-app.azurewebsites.net.")
+            ]
+        );
+        assert_eq!(findings[1].name, name("b.example.com."));
+        assert_eq!(findings[1].provider, "GitHub Pages");
+        assert_eq!(findings[1].confidence, Confidence::Low);
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{Certificate, ClientConfig, PrivateKey};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+
+/// How TLS connections to name servers are made and their certificates
+/// checked, for DNS over TLS and HTTPS and zone transfers over TLS
+/// (RFC 9103).
+#[derive(Clone)]
+pub struct Tls {
+    config: ClientConfig,
+    /// The name certificates are checked against, instead of the name
+    /// server's own.
+    server_name: Option<String>,
+    pub port: u16,
+}
+
+impl Tls {
+    /// Check certificates against the CAs in the PEM bundle `ca`, or the
+    /// bundled web PKI roots without one, and present the PEM certificate
+    /// chain and key of `client` when asked for one.
+    pub fn new(
+        ca: Option<&str>,
+        client: Option<(&str, &str)>,
+        server_name: Option<String>,
+        port: u16,
+    ) -> Result<Tls, String> {
+        let mut config = ClientConfig::new();
+        match ca {
+            Some(ca) => {
+                let certs = certificates(ca)?;
+                if certs.is_empty() {
+                    return Err("No certificates in the CA bundle".to_string());
+                }
+                for cert in &certs {
+                    config
+                        .root_store
+                        .add(cert)
+                        .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+                }
+            }
+            None => config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+        }
+        if let Some((chain, key)) = client {
+            config
+                .set_single_client_cert(certificates(chain)?, private_key(key)?)
+                .map_err(|e| format!("Invalid client certificate: {}", e))?;
+        }
+        Ok(Tls {
+            config,
+            server_name,
+            port,
+        })
+    }
+
+    /// Like [`Tls::new`], reading the CA bundle, client certificate chain
+    /// and key from files.
+    pub fn from_files(
+        ca: Option<&str>,
+        client: Option<(&str, &str)>,
+        server_name: Option<String>,
+        port: u16,
+    ) -> Result<Tls, String> {
+        let read = |path: &str| {
+            std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))
+        };
+        let ca = ca.map(read).transpose()?;
+        let client = match client {
+            Some((chain, key)) => Some((read(chain)?, read(key)?)),
+            None => None,
+        };
+        Tls::new(
+            ca.as_deref(),
+            client
+                .as_ref()
+                .map(|(chain, key)| (chain.as_str(), key.as_str())),
+            server_name,
+            port,
+        )
+    }
+
+    /// The name the certificate of the name server `host` is checked
+    /// against.
+    pub fn server_name<'a>(&'a self, host: &'a str) -> &'a str {
+        self.server_name
+            .as_deref()
+            .unwrap_or(host)
+            .trim_end_matches('.')
+    }
+
+    /// Make a TLS connection over `stream` to the name server `host`,
+    /// negotiating `protocol` (ALPN), `dot` for DNS and zone transfers
+    /// over TLS.
+    pub async fn connect(
+        &self,
+        stream: TcpStream,
+        host: &str,
+        protocol: &[u8],
+    ) -> Result<TlsStream<TcpStream>, String> {
+        let name = DNSNameRef::try_from_ascii_str(self.server_name(host)).map_err(|_| {
+            format!(
+                "no server name to check the certificate of {} against",
+                host
+            )
+        })?;
+        let mut config = self.config.clone();
+        config.alpn_protocols = vec![protocol.to_vec()];
+        TlsConnector::from(Arc::new(config))
+            .connect(name, stream)
+            .await
+            .map_err(|e| format!("handshake failed: {}", e))
+    }
+}
+
+fn certificates(pem: &str) -> Result<Vec<Certificate>, String> {
+    pemfile::certs(&mut pem.as_bytes()).map_err(|_| "Invalid PEM certificates".to_string())
+}
+
+/// The first PKCS #8 or RSA private key in `pem`.
+fn private_key(pem: &str) -> Result<PrivateKey, String> {
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut pem.as_bytes()).unwrap_or_default();
+    let rsa = pemfile::rsa_private_keys(&mut pem.as_bytes()).unwrap_or_default();
+    pkcs8
+        .into_iter()
+        .chain(rsa)
+        .next()
+        .ok_or_else(|| "No private key found".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (pem, key) = (
+            cert.serialize_pem().unwrap(),
+            cert.serialize_private_key_pem(),
+        );
+        assert!(Tls::new(Some(&pem), Some((&pem, &key)), None, 853).is_ok());
+        assert_eq!(
+            Tls::new(Some("not a certificate"), None, None, 853).err(),
+            Some("No certificates in the CA bundle".to_string())
+        );
+        assert_eq!(
+            Tls::new(None, Some((&pem, &pem)), None, 853).err(),
+            Some("No private key found".to_string())
+        );
+    }
+}
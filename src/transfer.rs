@@ -0,0 +1,1174 @@
+use crate::progress;
+use crate::resolver::NameServer;
+use crate::transport::{Connection, Error, Policy, Transport};
+use crate::tsig::{Key, TsigError};
+use colored::*;
+use futures::channel::mpsc::UnboundedSender;
+use futures::prelude::*;
+use futures::stream;
+use std::str::FromStr;
+use trust_dns_client::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::rdata::SOA;
+
+/// How a zone transfer from a name server went.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    /// The whole zone was transferred.
+    Allowed,
+    Refused,
+    /// The name server is not authoritative for the zone.
+    NotAuth,
+    Timeout,
+    ConnectionRefused,
+    /// The server stopped sending before the closing SOA record.
+    Truncated,
+    /// The closing SOA record differs from the opening one, the zone
+    /// changed during the transfer.
+    SoaMismatch,
+    /// The transfer failed part way through.
+    Aborted(String),
+    /// The response failed TSIG verification.
+    Tsig(TsigError),
+    /// The TLS connection could not be made.
+    Tls(String),
+    /// Any other failure or response code.
+    Failed(String),
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Allowed => write!(f, "allowed"),
+            Status::Refused => write!(f, "REFUSED"),
+            Status::NotAuth => write!(f, "NOTAUTH"),
+            Status::Timeout => write!(f, "timeout"),
+            Status::ConnectionRefused => write!(f, "connection refused"),
+            Status::Truncated => write!(f, "truncated"),
+            Status::SoaMismatch => write!(f, "SOA mismatch"),
+            Status::Aborted(e) => write!(f, "aborted: {}", e),
+            Status::Tsig(e) => write!(f, "TSIG: {}", e),
+            Status::Tls(e) => write!(f, "TLS: {}", e),
+            Status::Failed(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Status {
+        match e {
+            Error::Timeout => Status::Timeout,
+            Error::ConnectionRefused => Status::ConnectionRefused,
+            Error::Tsig(e) => Status::Tsig(e),
+            Error::Tls(e) => Status::Tls(e),
+            Error::Failed(e) => Status::Failed(e),
+        }
+    }
+}
+
+/// The zone transfer from one name server.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub name_server: NameServer,
+    /// TCP or TLS.
+    pub policy: Policy,
+    pub status: Status,
+    pub records: Vec<Record>,
+    /// The number of messages the records came in, and their size in
+    /// bytes.
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+impl Transfer {
+    /// The serial of the SOA record transferred.
+    pub fn serial(&self) -> Option<u32> {
+        self.records.iter().find_map(|record| match record.rdata() {
+            RData::SOA(soa) => Some(soa.serial()),
+            _ => None,
+        })
+    }
+}
+
+/// Ask every name server for a transfer of `domain` through each of
+/// `transports`, over TCP or TLS as their policies say, signing the
+/// requests with `key` when given.
+///
+/// Up to `concurrency` name servers are worked on at once, the transfers
+/// through each transport from one at the same time.  Records are sent to
+/// `found` as they arrive, labelled with the name server and policy they
+/// came by.
+pub async fn transfer(
+    domain: &str,
+    name_servers: &[NameServer],
+    concurrency: usize,
+    key: Option<&Key>,
+    transports: &[Transport],
+    found: &UnboundedSender<(NameServer, Policy, Record)>,
+) -> Vec<Transfer> {
+    let zone = match Name::from_str(domain) {
+        Ok(zone) => zone,
+        Err(_) => {
+            progress::note(format!("Failed to create name: {}", domain));
+            return vec![];
+        }
+    };
+    stream::iter(name_servers.iter().cloned())
+        .map(|name_server| {
+            let zone = zone.clone();
+            future::join_all(transports.iter().map(move |transport| {
+                transfer_from(zone.clone(), name_server.clone(), key, transport, found)
+            }))
+        })
+        .buffered(concurrency)
+        .flat_map(stream::iter)
+        .collect()
+        .await
+}
+
+async fn transfer_from(
+    zone: Name,
+    name_server: NameServer,
+    key: Option<&Key>,
+    transport: &Transport,
+    found: &UnboundedSender<(NameServer, Policy, Record)>,
+) -> Transfer {
+    let mut transfer = Transfer {
+        name_server,
+        policy: transport.policy.stream(),
+        status: Status::Truncated,
+        records: vec![],
+        messages: 0,
+        bytes: 0,
+    };
+    let request = transfer_request(&zone, RecordType::AXFR);
+    let connection = transport.connect(&transfer.name_server, request, key).await;
+    let mut connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            transfer.status = e.into();
+            return transfer;
+        }
+    };
+    transfer.status = loop {
+        let response = match next(&mut connection).await {
+            Ok(Some(response)) => response,
+            Ok(None) => break Status::Truncated,
+            Err(status) => break status,
+        };
+        if response.response_code() != ResponseCode::NoError {
+            break code_status(response.response_code());
+        }
+        let mut closing = None;
+        for record in response.answers() {
+            if transfer.records.is_empty() && record.record_type() != RecordType::SOA {
+                break;
+            }
+            if !transfer.records.is_empty() && record.record_type() == RecordType::SOA {
+                closing = Some(record.clone());
+            }
+            let _ = found.unbounded_send((
+                transfer.name_server.clone(),
+                transfer.policy,
+                record.clone(),
+            ));
+            transfer.records.push(record.clone());
+            if closing.is_some() {
+                break;
+            }
+        }
+        if transfer.records.is_empty() {
+            break Status::Failed("zone does not start with its SOA record".to_string());
+        }
+        if let Some(closing) = closing {
+            break if transfer.records.first() == Some(&closing) {
+                connection
+                    .finish()
+                    .map_or_else(Status::from, |_| Status::Allowed)
+            } else {
+                Status::SoaMismatch
+            };
+        }
+        if response.truncated() {
+            break Status::Truncated;
+        }
+    };
+    transfer.messages = connection.messages;
+    transfer.bytes = connection.bytes;
+    transfer
+}
+
+fn transfer_request(zone: &Name, record_type: RecordType) -> Message {
+    let mut message = Message::new();
+    message
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(false)
+        .add_query(Query::query(zone.clone(), record_type));
+    message
+}
+
+/// The next response of a transfer, None once the server closed the
+/// connection after at least one.
+///
+/// Failing after the first response aborts the transfer.
+async fn next(connection: &mut Connection) -> Result<Option<Message>, Status> {
+    match connection.read().await {
+        Ok(None) if connection.messages == 0 => Err(Status::Failed(
+            "connection closed without a response".to_string(),
+        )),
+        Err(e) if connection.messages > 0 && !matches!(e, Error::Tsig(_)) => {
+            Err(Status::Aborted(e.to_string()))
+        }
+        next => next.map_err(Status::from),
+    }
+}
+
+/// The serial of the zone a name server holds, from its SOA record.
+async fn current_serial(
+    zone: &Name,
+    name_server: &NameServer,
+    key: Option<&Key>,
+    transport: &Transport,
+) -> Option<u32> {
+    let request = transfer_request(zone, RecordType::SOA);
+    let response = transport.query(name_server, request, key).await.ok()?;
+    response
+        .answers()
+        .iter()
+        .find_map(|record| match record.rdata() {
+            RData::SOA(soa) => Some(soa.serial()),
+            _ => None,
+        })
+}
+
+fn code_status(code: ResponseCode) -> Status {
+    match code {
+        ResponseCode::Refused => Status::Refused,
+        ResponseCode::NotAuth => Status::NotAuth,
+        code => Status::Failed(code.to_string()),
+    }
+}
+
+/// A complete transfer starts and ends with the zone's SOA record.
+fn framed_by_soa(records: &[Record]) -> bool {
+    let is_soa = |record: Option<&Record>| record.map(Record::record_type) == Some(RecordType::SOA);
+    records.len() > 1 && is_soa(records.first()) && is_soa(records.last())
+}
+
+/// What an incremental transfer gave.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Changes {
+    /// The zone is no newer than the serial asked from.
+    UpToDate(u32),
+    /// The differences since the serial asked from, oldest first.
+    Incremental(Vec<Difference>),
+    /// The whole zone, sent when the history does not reach back to the
+    /// serial asked from.
+    FullZone(Vec<Record>),
+}
+
+/// The records removed and added between two serials of a zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub from: u32,
+    pub to: u32,
+    pub removed: Vec<Record>,
+    pub added: Vec<Record>,
+}
+
+/// The incremental transfer from one name server, next to how the same
+/// server answers a full transfer.
+#[derive(Debug, Clone)]
+pub struct IncrementalTransfer {
+    pub name_server: NameServer,
+    pub policy: Policy,
+    /// The serial of the zone the name server holds.
+    pub serial: Option<u32>,
+    pub status: Status,
+    pub changes: Option<Changes>,
+    pub axfr: Status,
+}
+
+impl IncrementalTransfer {
+    /// Whether the server gives away the history of a zone it does not
+    /// transfer in full.
+    pub fn leaks_history(&self) -> bool {
+        matches!(self.changes, Some(Changes::Incremental(_))) && self.axfr != Status::Allowed
+    }
+}
+
+/// Ask every name server for the changes to `domain` since `serial`, and
+/// for a full transfer to compare with, through each of `transports`,
+/// signing the requests with `key` when given.
+///
+/// Up to `concurrency` name servers are worked on at once.
+pub async fn incremental_transfer(
+    domain: &str,
+    name_servers: &[NameServer],
+    serial: u32,
+    concurrency: usize,
+    key: Option<&Key>,
+    transports: &[Transport],
+) -> Vec<IncrementalTransfer> {
+    let zone = match Name::from_str(domain) {
+        Ok(zone) => zone,
+        Err(_) => {
+            progress::note(format!("Failed to create name: {}", domain));
+            return vec![];
+        }
+    };
+    stream::iter(name_servers.iter().cloned())
+        .map(|name_server| {
+            let zone = zone.clone();
+            future::join_all(transports.iter().map(move |transport| {
+                incremental_transfer_from(zone.clone(), name_server.clone(), serial, key, transport)
+            }))
+        })
+        .buffered(concurrency)
+        .flat_map(stream::iter)
+        .collect()
+        .await
+}
+
+async fn incremental_transfer_from(
+    zone: Name,
+    name_server: NameServer,
+    serial: u32,
+    key: Option<&Key>,
+    transport: &Transport,
+) -> IncrementalTransfer {
+    let mut request = transfer_request(&zone, RecordType::IXFR);
+    // The serial the changes are wanted from goes in the authority section.
+    request.add_name_server(Record::from_rdata(
+        zone.clone(),
+        0,
+        RData::SOA(SOA::new(Name::root(), Name::root(), serial, 0, 0, 0, 0)),
+    ));
+    let changes = async {
+        let mut connection = transport.connect(&name_server, request, key).await?;
+        read_changes(&mut connection).await
+    };
+    // The records of the full transfer are not streamed.
+    let (found, _) = futures::channel::mpsc::unbounded();
+    let (current, axfr, changes) = future::join3(
+        current_serial(&zone, &name_server, key, transport),
+        transfer_from(zone.clone(), name_server.clone(), key, transport, &found),
+        changes,
+    )
+    .await;
+    // A server without IXFR may only do full transfers.
+    let unsupported = [ResponseCode::NotImp, ResponseCode::FormErr]
+        .iter()
+        .any(|code| changes.as_ref().err() == Some(&code_status(*code)));
+    let (status, changes) = match changes {
+        Err(_) if unsupported && axfr.status == Status::Allowed => {
+            (Status::Allowed, Some(Changes::FullZone(axfr.records)))
+        }
+        Ok(changes) => (Status::Allowed, Some(changes)),
+        Err(status) => (status, None),
+    };
+    IncrementalTransfer {
+        name_server,
+        policy: transport.policy.stream(),
+        serial: current,
+        status,
+        changes,
+        axfr: axfr.status,
+    }
+}
+
+/// Read the responses to an incremental transfer until the changes are
+/// complete.
+async fn read_changes(connection: &mut Connection) -> Result<Changes, Status> {
+    let mut answers = vec![];
+    loop {
+        let response = next(connection).await?.ok_or(Status::Truncated)?;
+        if response.response_code() != ResponseCode::NoError {
+            return Err(code_status(response.response_code()));
+        }
+        answers.extend(response.answers().iter().cloned());
+        if let Some(changes) = parse_changes(&answers) {
+            connection.finish()?;
+            return Ok(changes);
+        }
+        if response.truncated() {
+            return Err(Status::Truncated);
+        }
+    }
+}
+
+/// Read the answers of an incremental transfer, None when they stop
+/// short of the closing SOA record.
+///
+/// A single SOA record says the zone is up to date, a second record that
+/// is not a SOA record starts a full zone, and otherwise the answers are
+/// sequences of the old SOA record, the records removed, the new SOA
+/// record and the records added.
+fn parse_changes(answers: &[Record]) -> Option<Changes> {
+    let serial = |record: &Record| match record.rdata() {
+        RData::SOA(soa) => Some(soa.serial()),
+        _ => None,
+    };
+    let current = serial(answers.first()?)?;
+    if answers.len() == 1 {
+        return Some(Changes::UpToDate(current));
+    }
+    if answers.len() == 2 || serial(&answers[1]).is_none() {
+        return if framed_by_soa(answers) {
+            Some(Changes::FullZone(answers.to_vec()))
+        } else {
+            None
+        };
+    }
+    if serial(answers.last()?)? != current {
+        return None;
+    }
+    let mut differences = vec![];
+    let mut records = answers[1..answers.len() - 1].iter().peekable();
+    while let Some(record) = records.next() {
+        let from = serial(record)?;
+        let mut removed = vec![];
+        while let Some(record) = records.next_if(|record| serial(record).is_none()) {
+            removed.push(record.clone());
+        }
+        let to = serial(records.next()?)?;
+        let mut added = vec![];
+        while let Some(record) = records.next_if(|record| serial(record).is_none()) {
+            added.push(record.clone());
+        }
+        differences.push(Difference {
+            from,
+            to,
+            removed,
+            added,
+        });
+    }
+    Some(Changes::Incremental(differences))
+}
+
+/// The serial of the SOA record in a zone saved from an earlier run,
+/// either as results (`name:SOA:mname rname serial ...`) or as a master
+/// file (`name ttl IN SOA mname rname serial ...`).
+pub fn serial_from_zone(zone: &str) -> Result<u32, String> {
+    zone.lines()
+        .find_map(|line| {
+            let fields = match line.find(":SOA:") {
+                Some(at) => line[at + 5..].split_whitespace().collect::<Vec<_>>(),
+                None => {
+                    let fields = line.split_whitespace().collect::<Vec<_>>();
+                    let at = fields.iter().position(|field| *field == "SOA")?;
+                    fields[at + 1..].to_vec()
+                }
+            };
+            fields
+                .into_iter()
+                .filter(|field| *field != "(")
+                .nth(2)?
+                .parse::<u32>()
+                .ok()
+        })
+        .ok_or_else(|| "No SOA record with a serial found".to_string())
+}
+
+/// A table of the incremental transfer from each name server.
+pub fn incremental_report(transfers: &[IncrementalTransfer]) -> String {
+    let paths = path_notes(
+        &transfers
+            .iter()
+            .map(|transfer| {
+                (
+                    &transfer.name_server,
+                    transfer.policy,
+                    transfer.status == Status::Allowed,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    let mut report = format!(
+        "{:<40} {:<40} {:<4} {:>12} {:<20} {:<24} {:<20}  {}",
+        "Name server", "Address", "Via", "Serial", "IXFR", "Changes", "AXFR", "Note"
+    );
+    for (transfer, path) in transfers.iter().zip(paths) {
+        let changes = match &transfer.changes {
+            Some(Changes::UpToDate(serial)) => format!("up to date at {}", serial),
+            Some(Changes::Incremental(differences)) => format!(
+                "{} changes to {}",
+                differences.len(),
+                differences.last().map(|d| d.to).unwrap_or_default()
+            ),
+            Some(Changes::FullZone(records)) => format!("full zone, {} records", records.len()),
+            None => "-".to_string(),
+        };
+        let notes = [
+            path,
+            Some("leaks history").filter(|_| transfer.leaks_history()),
+        ];
+        report.push_str(&format!(
+            "\n{:<40} {:<40} {:<4} {:>12} {:<20} {:<24} {:<20}  {}",
+            transfer.name_server.host,
+            transfer.name_server.addr.ip().to_string(),
+            transfer.policy.to_string(),
+            transfer
+                .serial
+                .map(|serial| serial.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            transfer.status.to_string(),
+            changes,
+            transfer.axfr.to_string(),
+            notes
+                .iter()
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    report
+}
+
+/// Notes for the TLS transfers from name servers that allow a transfer
+/// over one of clear text and TLS but not the other, given the name
+/// server, policy and whether it was allowed of each transfer.
+fn path_notes(transfers: &[(&NameServer, Policy, bool)]) -> Vec<Option<&'static str>> {
+    transfers
+        .iter()
+        .map(|(name_server, policy, allowed)| {
+            let (_, _, clear) = transfers.iter().find(|(other, other_policy, _)| {
+                other == name_server && *other_policy == Policy::Tcp
+            })?;
+            match (policy, allowed, clear) {
+                (Policy::Tls, true, false) => Some("only over TLS"),
+                (Policy::Tls, false, true) => Some("only in clear text"),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// A table of the transfer from each name server.
+///
+/// Name servers that allowed the transfer are grouped by the zone they
+/// gave, the zone most of them agree on is numbered 1, and the rest are
+/// flagged as differing.
+pub fn report(transfers: &[Transfer]) -> String {
+    let zones = zone_numbers(transfers);
+    let paths = path_notes(
+        &transfers
+            .iter()
+            .map(|transfer| {
+                (
+                    &transfer.name_server,
+                    transfer.policy,
+                    transfer.status == Status::Allowed,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    let mut report = format!(
+        "{:<40} {:<40} {:<4} {:<20} {:>8} {:>8} {:>10} {:>12} {:>5}  {}",
+        "Name server",
+        "Address",
+        "Via",
+        "Status",
+        "Records",
+        "Messages",
+        "Bytes",
+        "Serial",
+        "Zone",
+        "Note"
+    );
+    for ((transfer, zone), path) in transfers.iter().zip(&zones).zip(&paths) {
+        let notes = [
+            Some("differs").filter(|_| zone.is_some_and(|zone| zone > 1)),
+            *path,
+        ];
+        report.push_str(&format!(
+            "\n{:<40} {:<40} {:<4} {:<20} {:>8} {:>8} {:>10} {:>12} {:>5}  {}",
+            transfer.name_server.host,
+            transfer.name_server.addr.ip().to_string(),
+            transfer.policy.to_string(),
+            transfer.status.to_string(),
+            transfer.records.len(),
+            transfer.messages,
+            transfer.bytes,
+            transfer
+                .serial()
+                .map(|serial| serial.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            zone.map(|zone| zone.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            notes
+                .iter()
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let clear_only = paths
+        .iter()
+        .filter(|path| **path == Some("only in clear text"))
+        .count();
+    if clear_only > 0 {
+        report.push_str(&format!(
+            "\n[{}] {} name servers transfer the zone in clear text but not over TLS",
+            "WRN".yellow(),
+            clear_only
+        ));
+    }
+    let differing = zones.iter().flatten().max().copied().unwrap_or(0);
+    if differing > 1 {
+        report.push_str(&format!(
+            "\n[{}] The name servers transferred {} different zones",
+            "WRN".yellow(),
+            differing
+        ));
+    }
+    report
+}
+
+/// Number the distinct zones of the allowed transfers, most common first.
+fn zone_numbers(transfers: &[Transfer]) -> Vec<Option<usize>> {
+    let zones = transfers
+        .iter()
+        .map(|transfer| {
+            if transfer.status == Status::Allowed {
+                let mut records = transfer.records.clone();
+                records.sort();
+                records.dedup();
+                Some(records)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut distinct: Vec<(&Vec<Record>, usize)> = vec![];
+    for zone in zones.iter().flatten() {
+        match distinct.iter_mut().find(|(known, _)| *known == zone) {
+            Some((_, count)) => *count += 1,
+            None => distinct.push((zone, 1)),
+        }
+    }
+    // A stable sort keeps the first seen first among equally common zones.
+    distinct.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    zones
+        .iter()
+        .map(|zone| {
+            zone.as_ref().and_then(|zone| {
+                distinct
+                    .iter()
+                    .position(|(known, _)| *known == zone)
+                    .map(|i| i + 1)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::Tls;
+    use crate::transport::Timeouts;
+    use futures::channel::mpsc;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_rustls::rustls::{
+        Certificate, NoClientAuth, PrivateKey, ServerConfig, ServerSession, Session, StreamOwned,
+    };
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn soa(serial: u32) -> Record {
+        Record::from_rdata(
+            name("example.com."),
+            60,
+            RData::SOA(SOA::new(
+                name("ns1.example.com."),
+                name("hostmaster.example.com."),
+                serial,
+                3600,
+                600,
+                86400,
+                60,
+            )),
+        )
+    }
+
+    fn a(host: &str, ip: &str) -> Record {
+        Record::from_rdata(name(host), 60, RData::A(ip.parse().unwrap()))
+    }
+
+    /// Serve a single zone transfer over TCP with the response code and
+    /// answers given, on a thread of its own.
+    fn stand_in_server(code: ResponseCode, answers: Vec<Record>) -> SocketAddr {
+        stand_in_transfers(move |_| (code, answers.clone()))
+    }
+
+    /// Serve zone transfers over TCP on a thread of their own, answering
+    /// each request with the response code and answers `answer` gives for
+    /// its type.
+    fn stand_in_transfers(
+        answer: impl Fn(RecordType) -> (ResponseCode, Vec<Record>) + Send + 'static,
+    ) -> SocketAddr {
+        stand_in_messages(move |record_type| {
+            let (code, answers) = answer(record_type);
+            (code, vec![answers])
+        })
+    }
+
+    /// Like [`stand_in_transfers`], sending the answers in one message
+    /// for each list `answer` gives.
+    fn stand_in_messages(
+        answer: impl Fn(RecordType) -> (ResponseCode, Vec<Vec<Record>>) + Send + 'static,
+    ) -> SocketAddr {
+        stand_in(None, answer)
+    }
+
+    /// Like [`stand_in_messages`], over TLS with `tls` when given.
+    fn stand_in(
+        tls: Option<Arc<ServerConfig>>,
+        answer: impl Fn(RecordType) -> (ResponseCode, Vec<Vec<Record>>) + Send + 'static,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                // Clients failing the TLS handshake are left to hang up.
+                match &tls {
+                    Some(config) => {
+                        let mut stream = StreamOwned::new(ServerSession::new(config), stream);
+                        if serve(&mut stream, &answer).is_ok() {
+                            stream.sess.send_close_notify();
+                            let _ = stream.flush();
+                        }
+                    }
+                    None => serve(&mut stream, &answer).unwrap(),
+                }
+            }
+        });
+        addr
+    }
+
+    /// Read a request off `stream` and write the responses `answer` gives.
+    fn serve(
+        stream: &mut (impl Read + Write),
+        answer: &impl Fn(RecordType) -> (ResponseCode, Vec<Vec<Record>>),
+    ) -> std::io::Result<()> {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut request = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut request)?;
+        let request = Message::from_vec(&request).unwrap();
+        let query = request.queries()[0].clone();
+        let (code, messages) = answer(query.query_type());
+        for answers in messages {
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_response_code(code)
+                .add_query(query.clone())
+                .add_answers(answers);
+            let bytes = response.to_vec().unwrap();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes())?;
+            stream.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Transfers over TCP alone.
+    fn tcp() -> Vec<Transport> {
+        vec![Transport::new(Policy::Tcp)]
+    }
+
+    fn name_server(host: &str, addr: SocketAddr) -> NameServer {
+        NameServer {
+            host: host.to_string(),
+            addr,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfer_statuses() {
+        let zone = vec![soa(7), a("www.example.com.", "10.0.0.1"), soa(7)];
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let name_servers = vec![
+            name_server("ns1", stand_in_server(ResponseCode::NoError, zone.clone())),
+            name_server("ns2", stand_in_server(ResponseCode::Refused, vec![])),
+            name_server("ns3", stand_in_server(ResponseCode::NotAuth, vec![])),
+            name_server(
+                "ns4",
+                stand_in_server(ResponseCode::NoError, zone[..2].to_vec()),
+            ),
+            name_server("ns5", closed),
+        ];
+        let (found, _) = mpsc::unbounded();
+        let transfers = transfer("example.com.", &name_servers, 5, None, &tcp(), &found).await;
+        let statuses = transfers
+            .iter()
+            .map(|transfer| transfer.status.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                Status::Allowed,
+                Status::Refused,
+                Status::NotAuth,
+                Status::Truncated,
+                Status::ConnectionRefused
+            ]
+        );
+        assert_eq!(transfers[0].records.len(), 3);
+        assert_eq!(transfers[0].serial(), Some(7));
+        assert_eq!(transfers[1].serial(), None);
+    }
+
+    #[tokio::test]
+    async fn test_silent_name_server_times_out() {
+        // Connections to it are accepted, but never answered.
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let zone = vec![soa(7), a("www.example.com.", "10.0.0.1"), soa(7)];
+        let name_servers = vec![
+            name_server("silent", silent.local_addr().unwrap()),
+            name_server("ns1", stand_in_server(ResponseCode::NoError, zone)),
+        ];
+        let mut transport = Transport::new(Policy::Tcp);
+        transport.timeouts = Timeouts {
+            connect: Duration::from_secs(1),
+            query: Duration::from_millis(500),
+        };
+        let (found, _) = mpsc::unbounded();
+        let started = std::time::Instant::now();
+        let transfers =
+            transfer("example.com.", &name_servers, 2, None, &[transport], &found).await;
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(transfers[0].status, Status::Timeout);
+        assert_eq!(transfers[1].status, Status::Allowed);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_over_several_messages() {
+        let www = a("www.example.com.", "10.0.0.1");
+        let api = a("api.example.com.", "10.0.0.2");
+        let complete = stand_in_messages({
+            let (www, api) = (www.clone(), api.clone());
+            move |_| {
+                (
+                    ResponseCode::NoError,
+                    vec![vec![soa(7), www.clone()], vec![api.clone()], vec![soa(7)]],
+                )
+            }
+        });
+        let changed = stand_in_messages({
+            let www = www.clone();
+            move |_| {
+                (
+                    ResponseCode::NoError,
+                    vec![vec![soa(7), www.clone()], vec![soa(8)]],
+                )
+            }
+        });
+        let cut_short = stand_in_messages(move |_| {
+            (
+                ResponseCode::NoError,
+                vec![vec![soa(7), www.clone()], vec![api.clone()]],
+            )
+        });
+        let name_servers = vec![
+            name_server("ns1", complete),
+            name_server("ns2", changed),
+            name_server("ns3", cut_short),
+        ];
+        let (found, received) = mpsc::unbounded();
+        let transfers = transfer("example.com.", &name_servers, 3, None, &tcp(), &found).await;
+        drop(found);
+        assert_eq!(transfers[0].status, Status::Allowed);
+        assert_eq!(transfers[0].records.len(), 4);
+        assert_eq!(transfers[0].messages, 3);
+        assert!(transfers[0].bytes > 0);
+        assert_eq!(transfers[1].status, Status::SoaMismatch);
+        assert_eq!(transfers[2].status, Status::Truncated);
+        assert_eq!(transfers[2].messages, 2);
+        assert_eq!(received.collect::<Vec<_>>().await.len(), 4 + 3 + 3);
+    }
+
+    #[tokio::test]
+    async fn test_signed_transfer_needs_signed_response() {
+        let key = "key \"transfer\" { algorithm hmac-sha256; secret \"c2VjcmV0\"; };"
+            .parse::<Key>()
+            .unwrap();
+        let zone = vec![soa(7), a("www.example.com.", "10.0.0.1"), soa(7)];
+        let server = stand_in_server(ResponseCode::NoError, zone);
+        let (found, _) = mpsc::unbounded();
+        let transfers = transfer(
+            "example.com.",
+            &[name_server("ns1", server)],
+            1,
+            Some(&key),
+            &tcp(),
+            &found,
+        )
+        .await;
+        assert_eq!(transfers[0].status, Status::Tsig(TsigError::Unsigned));
+        assert!(transfers[0].records.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transfer_over_tls() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(
+                vec![Certificate(cert.serialize_der().unwrap())],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        config.set_protocols(&[b"dot".to_vec()]);
+        let zone = vec![soa(7), a("www.example.com.", "10.0.0.1"), soa(7)];
+        let clear = stand_in_server(ResponseCode::Refused, vec![]);
+        let encrypted = stand_in(Some(Arc::new(config)), move |_| {
+            (ResponseCode::NoError, vec![zone.clone()])
+        });
+        let transports = |ca: &rcgen::Certificate| {
+            let mut tls = Transport::new(Policy::Tls);
+            tls.tls = Some(
+                Tls::new(
+                    Some(&ca.serialize_pem().unwrap()),
+                    None,
+                    Some("localhost".to_string()),
+                    encrypted.port(),
+                )
+                .unwrap(),
+            );
+            vec![Transport::new(Policy::Tcp), tls]
+        };
+        let name_servers = [name_server("ns1", clear)];
+        let (found, _) = mpsc::unbounded();
+        let transfers = transfer(
+            "example.com.",
+            &name_servers,
+            1,
+            None,
+            &transports(&cert),
+            &found,
+        )
+        .await;
+        assert_eq!(
+            transfers
+                .iter()
+                .map(|transfer| (transfer.policy, transfer.status.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Policy::Tcp, Status::Refused),
+                (Policy::Tls, Status::Allowed)
+            ]
+        );
+        assert_eq!(transfers[1].records.len(), 3);
+        let report = report(&transfers);
+        assert!(report.lines().nth(2).unwrap().ends_with("only over TLS"));
+        // A certificate the CA bundle does not vouch for fails.
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let transfers = transfer(
+            "example.com.",
+            &name_servers,
+            1,
+            None,
+            &transports(&other),
+            &found,
+        )
+        .await;
+        assert!(matches!(transfers[1].status, Status::Tls(_)));
+    }
+
+    #[test]
+    fn test_report_flags_differing_zones() {
+        let addr = "192.0.2.1:53".parse().unwrap();
+        let allowed = |records: Vec<Record>| Transfer {
+            name_server: name_server("ns.example.com.", addr),
+            policy: Policy::Tcp,
+            status: Status::Allowed,
+            records,
+            messages: 1,
+            bytes: 120,
+        };
+        let zone = vec![soa(7), a("www.example.com.", "10.0.0.1"), soa(7)];
+        let stale = vec![soa(6), a("www.example.com.", "10.0.0.2"), soa(6)];
+        let transfers = vec![
+            allowed(stale),
+            allowed(zone.clone()),
+            allowed(zone.into_iter().rev().collect()),
+            Transfer {
+                name_server: name_server("ns.example.com.", addr),
+                policy: Policy::Tcp,
+                status: Status::Refused,
+                records: vec![],
+                messages: 1,
+                bytes: 29,
+            },
+        ];
+        assert_eq!(
+            zone_numbers(&transfers),
+            vec![Some(2), Some(1), Some(1), None]
+        );
+        let report = report(&transfers);
+        let rows = report
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows[1],
+            vec![
+                "ns.example.com.",
+                "192.0.2.1",
+                "TCP",
+                "allowed",
+                "3",
+                "1",
+                "120",
+                "6",
+                "2",
+                "differs"
+            ]
+        );
+        assert_eq!(
+            rows[4],
+            vec![
+                "ns.example.com.",
+                "192.0.2.1",
+                "TCP",
+                "REFUSED",
+                "0",
+                "1",
+                "29",
+                "-",
+                "-"
+            ]
+        );
+        assert!(report.ends_with("The name servers transferred 2 different zones"));
+    }
+
+    #[test]
+    fn test_parse_changes() {
+        let www = a("www.example.com.", "10.0.0.1");
+        let api = a("api.example.com.", "10.0.0.2");
+        let mail = a("mail.example.com.", "10.0.0.3");
+        assert_eq!(parse_changes(&[soa(9)]), Some(Changes::UpToDate(9)));
+        let full = vec![soa(9), www.clone(), soa(9)];
+        assert_eq!(parse_changes(&full), Some(Changes::FullZone(full.clone())));
+        let history = vec![
+            soa(9),
+            soa(7),
+            www.clone(),
+            soa(8),
+            api.clone(),
+            soa(8),
+            soa(9),
+            mail.clone(),
+            soa(9),
+        ];
+        assert_eq!(
+            parse_changes(&history),
+            Some(Changes::Incremental(vec![
+                Difference {
+                    from: 7,
+                    to: 8,
+                    removed: vec![www],
+                    added: vec![api],
+                },
+                Difference {
+                    from: 8,
+                    to: 9,
+                    removed: vec![],
+                    added: vec![mail],
+                },
+            ]))
+        );
+        assert_eq!(parse_changes(&history[..5]), None);
+        assert_eq!(parse_changes(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_incremental_transfer_leaks_history() {
+        // trust-dns reads the IXFR type back as an unknown type.
+        let server = stand_in_transfers(|record_type| match record_type {
+            RecordType::AXFR => (ResponseCode::Refused, vec![]),
+            _ => (
+                ResponseCode::NoError,
+                vec![
+                    soa(8),
+                    soa(7),
+                    a("old.example.com.", "10.0.0.1"),
+                    soa(8),
+                    soa(8),
+                ],
+            ),
+        });
+        let transfers = incremental_transfer(
+            "example.com.",
+            &[name_server("ns1", server)],
+            7,
+            1,
+            None,
+            &tcp(),
+        )
+        .await;
+        assert_eq!(transfers[0].serial, Some(8));
+        assert_eq!(transfers[0].status, Status::Allowed);
+        assert_eq!(transfers[0].axfr, Status::Refused);
+        assert!(transfers[0].leaks_history());
+        let report = incremental_report(&transfers);
+        assert!(report.lines().nth(1).unwrap().ends_with("leaks history"));
+    }
+
+    #[tokio::test]
+    async fn test_incremental_transfer_falls_back_to_axfr() {
+        let server = stand_in_transfers(|record_type| match record_type {
+            RecordType::AXFR => (
+                ResponseCode::NoError,
+                vec![soa(9), a("www.example.com.", "10.0.0.1"), soa(9)],
+            ),
+            _ => (ResponseCode::NotImp, vec![]),
+        });
+        let transfers = incremental_transfer(
+            "example.com.",
+            &[name_server("ns1", server)],
+            7,
+            1,
+            None,
+            &tcp(),
+        )
+        .await;
+        assert_eq!(transfers[0].status, Status::Allowed);
+        assert!(matches!(
+            &transfers[0].changes,
+            Some(Changes::FullZone(records)) if records.len() == 3
+        ));
+        assert!(!transfers[0].leaks_history());
+    }
+
+    #[test]
+    fn test_serial_from_zone() {
+        assert_eq!(
+            serial_from_zone(
+                "[ns1/192.0.2.1] example.com.:SOA:ns1.example.com. hostmaster.example.com. 2021 3600 600 86400 60"
+            ),
+            Ok(2021)
+        );
+        assert_eq!(
+            serial_from_zone(
+                "example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. ( 2022 3600 600 86400 60 )"
+            ),
+            Ok(2022)
+        );
+        assert!(serial_from_zone("www.example.com.:A:10.0.0.1").is_err());
+    }
+}
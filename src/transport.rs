@@ -0,0 +1,711 @@
+use crate::resolver::NameServer;
+use crate::tls::Tls;
+use crate::tsig::{Key, TsigError, Verifier};
+use crate::udp_engine::UdpEngine;
+use rand::{thread_rng, Rng};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use trust_dns_proto::error::{ProtoError, ProtoErrorKind};
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query};
+
+/// The port DNS over HTTPS is served on by default.
+const HTTPS_PORT: u16 = 443;
+
+/// How queries reach a name server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    Udp,
+    Tcp,
+    /// UDP, asking again over TCP when the answer is truncated.
+    UdpTcp,
+    /// TLS, on its own port.
+    Tls,
+    /// HTTPS (RFC 8484).
+    Https,
+}
+
+impl Policy {
+    /// The policy for queries answered in more than one message, TLS for
+    /// TLS and TCP for the rest.
+    pub fn stream(self) -> Policy {
+        match self {
+            Policy::Tls => Policy::Tls,
+            _ => Policy::Tcp,
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Policy, String> {
+        match policy {
+            "udp" => Ok(Policy::Udp),
+            "tcp" => Ok(Policy::Tcp),
+            "udp-tcp" => Ok(Policy::UdpTcp),
+            "tls" => Ok(Policy::Tls),
+            "https" => Ok(Policy::Https),
+            _ => Err(format!("Unknown transport {}", policy)),
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Policy::Udp => write!(f, "UDP"),
+            Policy::Tcp => write!(f, "TCP"),
+            Policy::UdpTcp => write!(f, "UDP/TCP"),
+            Policy::Tls => write!(f, "TLS"),
+            Policy::Https => write!(f, "HTTPS"),
+        }
+    }
+}
+
+/// How long to wait for a name server, to connect to it and for each
+/// answer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub query: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(5),
+            query: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Why a query got no answer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Timeout,
+    ConnectionRefused,
+    /// The response failed TSIG verification.
+    Tsig(TsigError),
+    /// The TLS connection could not be made.
+    Tls(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "timeout"),
+            Error::ConnectionRefused => write!(f, "connection refused"),
+            Error::Tsig(e) => write!(f, "TSIG: {}", e),
+            Error::Tls(e) => write!(f, "TLS: {}", e),
+            Error::Failed(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+/// How queries are sent to name servers, and what they ask for.
+#[derive(Clone)]
+pub struct Transport {
+    pub policy: Policy,
+    /// The UDP payload size advertised with EDNS, None to send queries
+    /// without EDNS.
+    pub edns: Option<u16>,
+    /// Ask for the DNSSEC records of the answers (the DO bit).
+    pub dnssec_ok: bool,
+    /// Ask resolvers not to validate the answers (the CD bit).
+    pub checking_disabled: bool,
+    pub timeouts: Timeouts,
+    /// How TLS and HTTPS connections are made, needed for those policies.
+    pub tls: Option<Tls>,
+    /// The path DNS over HTTPS queries are posted to.
+    pub path: String,
+    /// The port DNS over HTTPS queries are posted to.
+    pub https_port: u16,
+    /// The shared sockets UDP queries go out on, a socket is bound for
+    /// each query without them.
+    pub sockets: Option<UdpEngine>,
+}
+
+/// A query message asking `query`, with recursion when `recursion_desired`.
+pub fn request(query: Query, recursion_desired: bool) -> Message {
+    let mut request = Message::new();
+    request
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(recursion_desired)
+        .add_query(query);
+    request
+}
+
+impl Transport {
+    /// Queries with `policy`, advertising a 1232 byte EDNS payload and the
+    /// default timeouts.
+    pub fn new(policy: Policy) -> Transport {
+        Transport {
+            policy,
+            edns: Some(1232),
+            dnssec_ok: false,
+            checking_disabled: false,
+            timeouts: Timeouts::default(),
+            tls: None,
+            path: "/dns-query".to_string(),
+            https_port: HTTPS_PORT,
+            sockets: None,
+        }
+    }
+
+    /// The same transport with another policy.
+    pub fn with_policy(&self, policy: Policy) -> Transport {
+        Transport {
+            policy,
+            ..self.clone()
+        }
+    }
+
+    /// The same transport sending its UDP queries over `sockets`.
+    pub fn with_sockets(&self, sockets: UdpEngine) -> Transport {
+        Transport {
+            sockets: Some(sockets),
+            ..self.clone()
+        }
+    }
+
+    /// Send `request` to the name server, signed with `key` when given,
+    /// and read back the answer.
+    pub async fn query(
+        &self,
+        name_server: &NameServer,
+        mut request: Message,
+        key: Option<&Key>,
+    ) -> Result<Message, Error> {
+        match self.policy {
+            Policy::Udp | Policy::UdpTcp => {
+                let (response, verifier) = match (&self.sockets, key) {
+                    // The shared sockets pick the ID, which a signature
+                    // covers, so signed queries get a socket of their own.
+                    (Some(sockets), None) => {
+                        self.prepare(&mut request);
+                        let wait = self.timeouts.query;
+                        let response = sockets
+                            .query(request.clone(), name_server.addr, wait)
+                            .await?;
+                        (response, None)
+                    }
+                    _ => {
+                        let (bytes, mut verifier) = self.encode(&mut request, key)?;
+                        let response = self.udp(name_server, &bytes, request.id()).await?;
+                        let response = decode(&response, request.id(), verifier.as_mut())?;
+                        (response, verifier)
+                    }
+                };
+                if response.truncated() && self.policy == Policy::UdpTcp {
+                    return self.query_stream(name_server, request, key).await;
+                }
+                finish(verifier.as_ref())?;
+                Ok(response)
+            }
+            Policy::Tcp | Policy::Tls => self.query_stream(name_server, request, key).await,
+            Policy::Https => {
+                let (bytes, mut verifier) = self.encode(&mut request, key)?;
+                let response = self.https(name_server, &bytes).await?;
+                let response = decode(&response, request.id(), verifier.as_mut())?;
+                finish(verifier.as_ref())?;
+                Ok(response)
+            }
+        }
+    }
+
+    /// Send `request` over TCP, or TLS for the TLS policy, and read back
+    /// the answer.
+    async fn query_stream(
+        &self,
+        name_server: &NameServer,
+        request: Message,
+        key: Option<&Key>,
+    ) -> Result<Message, Error> {
+        let mut connection = self.connect(name_server, request, key).await?;
+        let response = connection
+            .read()
+            .await?
+            .ok_or_else(|| Error::Failed("connection closed without a response".to_string()))?;
+        connection.finish()?;
+        Ok(response)
+    }
+
+    /// Connect to the name server, over TLS for the TLS policy and TCP
+    /// otherwise, and send it `request`, signed with `key` when given,
+    /// for its answers to be read off the connection.
+    pub async fn connect(
+        &self,
+        name_server: &NameServer,
+        mut request: Message,
+        key: Option<&Key>,
+    ) -> Result<Connection, Error> {
+        let (bytes, verifier) = self.encode(&mut request, key)?;
+        let mut stream = match self.policy.stream() {
+            Policy::Tls => {
+                let port = self.tls()?.port;
+                self.open(name_server, port, Some(b"dot".as_ref())).await?
+            }
+            _ => {
+                self.open(name_server, name_server.addr.port(), None)
+                    .await?
+            }
+        };
+        stream
+            .write_all(&(bytes.len() as u16).to_be_bytes())
+            .await
+            .map_err(|e| io_failure(&e))?;
+        stream.write_all(&bytes).await.map_err(|e| io_failure(&e))?;
+        Ok(Connection {
+            stream,
+            id: request.id(),
+            timeout: self.timeouts.query,
+            verifier,
+            messages: 0,
+            bytes: 0,
+        })
+    }
+
+    fn tls(&self) -> Result<&Tls, Error> {
+        self.tls
+            .as_ref()
+            .ok_or_else(|| Error::Tls("no TLS configuration".to_string()))
+    }
+
+    /// Give `request` a fresh id and the EDNS options and flags asked for,
+    /// and encode it, signed with `key` when given.
+    fn encode(
+        &self,
+        request: &mut Message,
+        key: Option<&Key>,
+    ) -> Result<(Vec<u8>, Option<Verifier>), Error> {
+        self.prepare(request);
+        match key {
+            Some(key) => {
+                let (bytes, verifier) = key.sign(request).map_err(Error::Failed)?;
+                Ok((bytes, Some(verifier)))
+            }
+            None => Ok((request.to_vec().map_err(|e| proto_failure(&e))?, None)),
+        }
+    }
+
+    /// Give `request` a fresh id and the EDNS options and flags asked for.
+    fn prepare(&self, request: &mut Message) {
+        request
+            .set_id(thread_rng().gen())
+            .set_checking_disabled(self.checking_disabled);
+        if let Some(size) = self.edns {
+            let mut edns = Edns::new();
+            edns.set_max_payload(size);
+            edns.set_dnssec_ok(self.dnssec_ok);
+            request.set_edns(edns);
+        }
+    }
+
+    /// Connect to the name server on `port`, over TLS negotiating
+    /// `protocol` when one is given.
+    async fn open(
+        &self,
+        name_server: &NameServer,
+        port: u16,
+        protocol: Option<&[u8]>,
+    ) -> Result<Box<dyn Stream>, Error> {
+        let addr = SocketAddr::new(name_server.addr.ip(), port);
+        let stream = timeout(self.timeouts.connect, TcpStream::connect(addr))
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(|e| io_failure(&e))?;
+        let protocol = match protocol {
+            Some(protocol) => protocol,
+            None => return Ok(Box::new(stream)),
+        };
+        let connect = self.tls()?.connect(stream, &name_server.host, protocol);
+        let stream = timeout(self.timeouts.connect, connect)
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Error::Tls)?;
+        Ok(Box::new(stream))
+    }
+
+    /// Send a query over UDP and wait for the datagram answering it.
+    async fn udp(&self, name_server: &NameServer, query: &[u8], id: u16) -> Result<Vec<u8>, Error> {
+        let local = if name_server.addr.is_ipv4() {
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+        } else {
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+        };
+        let mut socket = UdpSocket::bind(local).await.map_err(|e| io_failure(&e))?;
+        socket
+            .send_to(query, name_server.addr)
+            .await
+            .map_err(|e| io_failure(&e))?;
+        let receive = async {
+            let mut buf = vec![0; u16::MAX as usize];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await?;
+                // Datagrams from elsewhere or for other queries are not the
+                // answer.
+                if from == name_server.addr && len >= 2 && buf[..2] == id.to_be_bytes() {
+                    return Ok::<_, std::io::Error>(buf[..len].to_vec());
+                }
+            }
+        };
+        timeout(self.timeouts.query, receive)
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(|e| io_failure(&e))
+    }
+
+    /// Post a query over HTTPS and read back the answer.
+    async fn https(&self, name_server: &NameServer, query: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut stream = self
+            .open(name_server, self.https_port, Some(b"http/1.1".as_ref()))
+            .await?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Content-Type: application/dns-message\r\n\
+             Accept: application/dns-message\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.path,
+            self.tls()?.server_name(&name_server.host),
+            query.len()
+        );
+        let exchange = async {
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(query).await?;
+            let mut response = vec![];
+            let mut buf = [0; 4096];
+            // Servers may hang up without closing TLS, so reading stops
+            // once the whole answer is in.
+            while http_body(&response, false) == Ok(None) {
+                match stream.read(&mut buf).await? {
+                    0 => break,
+                    len => response.extend_from_slice(&buf[..len]),
+                }
+            }
+            Ok::<_, std::io::Error>(response)
+        };
+        let response = timeout(self.timeouts.query, exchange)
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(|e| io_failure(&e))?;
+        http_body(&response, true)
+            .map(Option::unwrap_or_default)
+            .map_err(Error::Failed)
+    }
+}
+
+/// What a connection reads and writes, TCP or TLS over it.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// A connection to a name server a query was sent on, its answers read
+/// off it in turn.
+pub struct Connection {
+    stream: Box<dyn Stream>,
+    id: u16,
+    /// How long to wait for each answer.
+    timeout: Duration,
+    /// Checks the answers when the query is signed.
+    verifier: Option<Verifier>,
+    /// The number of answers read and their size in bytes.
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+impl Connection {
+    /// The next answer, None once the server closed the connection.
+    pub async fn read(&mut self) -> Result<Option<Message>, Error> {
+        let bytes = match read_message(&mut self.stream, self.timeout).await? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let response = decode(&bytes, self.id, self.verifier.as_mut())?;
+        self.messages += 1;
+        self.bytes += bytes.len();
+        Ok(Some(response))
+    }
+
+    /// Check the last answer read was signed, when the query was.
+    pub fn finish(&self) -> Result<(), Error> {
+        finish(self.verifier.as_ref())
+    }
+}
+
+/// Read a length prefixed message off `stream` within `timeout`, None
+/// when the stream ends before it.
+async fn read_message(
+    stream: &mut Box<dyn Stream>,
+    timeout: Duration,
+) -> Result<Option<Vec<u8>>, Error> {
+    let read = async {
+        let mut len = [0; 2];
+        match stream.read_exact(&mut len).await {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        };
+        let mut message = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message).await?;
+        Ok::<_, std::io::Error>(Some(message))
+    };
+    match tokio::time::timeout(timeout, read).await {
+        Ok(Ok(message)) => Ok(message),
+        Ok(Err(e)) => Err(io_failure(&e)),
+        Err(_) => Err(Error::Timeout),
+    }
+}
+
+/// Check an answer is signed when the query was, and is to the query
+/// with `id`.
+fn decode(bytes: &[u8], id: u16, verifier: Option<&mut Verifier>) -> Result<Message, Error> {
+    if let Some(verifier) = verifier {
+        verifier.verify(bytes).map_err(Error::Tsig)?;
+    }
+    let response = Message::from_vec(bytes).map_err(|e| proto_failure(&e))?;
+    if response.id() != id {
+        return Err(Error::Failed("response to another request".to_string()));
+    }
+    Ok(response)
+}
+
+fn finish(verifier: Option<&Verifier>) -> Result<(), Error> {
+    match verifier {
+        Some(verifier) => verifier.finish().map_err(Error::Tsig),
+        None => Ok(()),
+    }
+}
+
+/// The body of an HTTP/1.1 response, None while more of it is to come.
+///
+/// A body without a length runs to the end of the response, `ended` says
+/// whether it has.
+fn http_body(response: &[u8], ended: bool) -> Result<Option<Vec<u8>>, String> {
+    let incomplete = || {
+        if ended {
+            Err("HTTP response cut short".to_string())
+        } else {
+            Ok(None)
+        }
+    };
+    let end = match find(response, b"\r\n\r\n") {
+        Some(end) => end,
+        None => return incomplete(),
+    };
+    let head = String::from_utf8_lossy(&response[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("HTTP {}", status));
+    }
+    let mut length = None;
+    let mut chunked = false;
+    for line in lines {
+        let mut header = line.splitn(2, ':');
+        let name = header
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let value = header.next().unwrap_or_default().trim();
+        match name.as_str() {
+            "content-length" => length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => (),
+        }
+    }
+    let body = &response[end + 4..];
+    if chunked {
+        return dechunk(body).map_or_else(incomplete, |body| Ok(Some(body)));
+    }
+    match length {
+        Some(length) if body.len() >= length => Ok(Some(body[..length].to_vec())),
+        None if ended => Ok(Some(body.to_vec())),
+        _ => incomplete(),
+    }
+}
+
+/// The data of a chunked body, None when it stops short of the last
+/// chunk.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    loop {
+        let line = find(body, b"\r\n")?;
+        let size = String::from_utf8_lossy(&body[..line]);
+        // Chunk extensions follow the size.
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line + 2..];
+        if size == 0 {
+            return Some(data);
+        }
+        if body.len() < size + 2 {
+            return None;
+        }
+        data.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn proto_failure(e: &ProtoError) -> Error {
+    match e.kind() {
+        ProtoErrorKind::Timeout => Error::Timeout,
+        ProtoErrorKind::Io(io) => io_failure(io),
+        // The proto error's own message carries a backtrace.
+        kind => Error::Failed(kind.to_string()),
+    }
+}
+
+fn io_failure(e: &std::io::Error) -> Error {
+    match e.kind() {
+        std::io::ErrorKind::ConnectionRefused => Error::ConnectionRefused,
+        std::io::ErrorKind::TimedOut => Error::Timeout,
+        _ => Error::Failed(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use trust_dns_proto::op::{MessageType, Query};
+    use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+    fn request() -> Message {
+        let mut request = Message::new();
+        request.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        request
+    }
+
+    /// The answer to `request`, with an A record for `ip`.
+    fn answer(request: &[u8], ip: &str) -> Message {
+        let request = Message::from_vec(request).unwrap();
+        let query = request.queries()[0].clone();
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .add_answer(Record::from_rdata(
+                query.name().clone(),
+                60,
+                RData::A(ip.parse().unwrap()),
+            ))
+            .add_query(query);
+        response
+    }
+
+    fn name_server(addr: SocketAddr) -> NameServer {
+        NameServer {
+            host: "localhost".to_string(),
+            addr,
+        }
+    }
+
+    #[test]
+    fn test_encode_sets_edns_options() {
+        let mut transport = Transport::new(Policy::Udp);
+        transport.edns = Some(4096);
+        transport.dnssec_ok = true;
+        transport.checking_disabled = true;
+        let (bytes, _) = transport.encode(&mut request(), None).unwrap();
+        let sent = Message::from_vec(&bytes).unwrap();
+        assert!(sent.checking_disabled());
+        let edns = sent.edns().unwrap();
+        assert_eq!(edns.max_payload(), 4096);
+        assert!(edns.dnssec_ok());
+        transport.edns = None;
+        let (bytes, _) = transport.encode(&mut request(), None).unwrap();
+        assert!(Message::from_vec(&bytes).unwrap().edns().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_udp_falls_back_to_tcp_on_truncation() {
+        // The truncated answer over UDP and the whole one over TCP, on the
+        // same port.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = std::net::UdpSocket::bind(addr).unwrap();
+        std::thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (len, from) = socket.recv_from(&mut buf).unwrap();
+            let mut response = answer(&buf[..len], "10.0.0.1");
+            response.take_answers();
+            // Encoding a message clears its TC bit, it is set in the bytes.
+            let mut bytes = response.to_vec().unwrap();
+            bytes[2] |= 0x02;
+            socket.send_to(&bytes, from).unwrap();
+        });
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut request = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut request).unwrap();
+                let bytes = answer(&request, "10.0.0.2").to_vec().unwrap();
+                stream
+                    .write_all(&(bytes.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&bytes).unwrap();
+            }
+        });
+        let udp = Transport::new(Policy::Udp)
+            .query(&name_server(addr), request(), None)
+            .await
+            .unwrap();
+        assert!(udp.truncated());
+        assert!(udp.answers().is_empty());
+        let fallback = Transport::new(Policy::UdpTcp)
+            .query(&name_server(addr), request(), None)
+            .await
+            .unwrap();
+        assert!(!fallback.truncated());
+        assert_eq!(
+            fallback.answers()[0].rdata(),
+            &RData::A("10.0.0.2".parse().unwrap())
+        );
+        let sockets = UdpEngine::new(&[addr]).await.unwrap();
+        let shared = Transport::new(Policy::UdpTcp)
+            .with_sockets(sockets)
+            .query(&name_server(addr), request(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            shared.answers()[0].rdata(),
+            &RData::A("10.0.0.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_http_body() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(http_body(response, false), Ok(Some(b"abc".to_vec())));
+        assert_eq!(http_body(&response[..response.len() - 1], false), Ok(None));
+        assert!(http_body(&response[..response.len() - 1], true).is_err());
+        let chunked = b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n2\r\nab\r\n1;x=y\r\nc\r\n0\r\n\r\n";
+        assert_eq!(http_body(chunked, false), Ok(Some(b"abc".to_vec())));
+        let unbounded = b"HTTP/1.1 200 OK\r\n\r\nabc";
+        assert_eq!(http_body(unbounded, false), Ok(None));
+        assert_eq!(http_body(unbounded, true), Ok(Some(b"abc".to_vec())));
+        assert_eq!(
+            http_body(b"HTTP/1.1 415 Unsupported Media Type\r\n\r\n", true),
+            Err("HTTP HTTP/1.1 415 Unsupported Media Type".to_string())
+        );
+    }
+}
@@ -0,0 +1,604 @@
+use crate::wildcard::to_fqdn;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use trust_dns_proto::error::ProtoError;
+use trust_dns_proto::op::Message;
+use trust_dns_proto::rr::Name;
+use trust_dns_proto::serialize::binary::{BinDecodable, BinDecoder};
+
+/// The TSIG record type.
+const TSIG: u16 = 250;
+
+/// The class TSIG records are sent in, ANY.
+const ANY: u16 = 255;
+
+/// The seconds the time a request is signed at may be off at the server.
+const FUDGE: u16 = 300;
+
+/// The most messages of a transfer a server may leave unsigned in a row.
+const MAX_UNSIGNED: usize = 99;
+
+/// A TSIG MAC algorithm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    HmacMd5,
+    HmacSha1,
+    HmacSha224,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl Algorithm {
+    /// The name the algorithm goes by in TSIG records.
+    fn name(self) -> Name {
+        let name = match self {
+            Algorithm::HmacMd5 => "hmac-md5.sig-alg.reg.int.",
+            Algorithm::HmacSha1 => "hmac-sha1.",
+            Algorithm::HmacSha224 => "hmac-sha224.",
+            Algorithm::HmacSha256 => "hmac-sha256.",
+            Algorithm::HmacSha384 => "hmac-sha384.",
+            Algorithm::HmacSha512 => "hmac-sha512.",
+        };
+        Name::from_ascii(name).expect("algorithm names are valid")
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    /// Parse an algorithm as BIND names it, e.g. `hmac-sha256`.
+    fn from_str(algorithm: &str) -> Result<Algorithm, String> {
+        match algorithm.trim_end_matches('.').to_lowercase().as_str() {
+            "hmac-md5" | "hmac-md5.sig-alg.reg.int" => Ok(Algorithm::HmacMd5),
+            "hmac-sha1" => Ok(Algorithm::HmacSha1),
+            "hmac-sha224" => Ok(Algorithm::HmacSha224),
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            "hmac-sha384" => Ok(Algorithm::HmacSha384),
+            "hmac-sha512" => Ok(Algorithm::HmacSha512),
+            _ => Err(format!("Unsupported TSIG algorithm {}", algorithm)),
+        }
+    }
+}
+
+/// Why a response failed TSIG verification.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TsigError {
+    /// The response carried no TSIG record.
+    Unsigned,
+    /// The server refused the request's signature with this TSIG error.
+    Rejected(u16),
+    /// The response was signed with another key or algorithm.
+    WrongKey,
+    /// The MAC of the response does not match.
+    BadSig,
+    /// The response was signed too far from the current time.
+    BadTime,
+    Malformed(String),
+}
+
+impl std::fmt::Display for TsigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TsigError::Unsigned => write!(f, "response not signed"),
+            TsigError::Rejected(16) => write!(f, "server rejected the signature (BADSIG)"),
+            TsigError::Rejected(17) => write!(f, "server does not know the key (BADKEY)"),
+            TsigError::Rejected(18) => write!(f, "server clock is off (BADTIME)"),
+            TsigError::Rejected(error) => write!(f, "server rejected the request ({})", error),
+            TsigError::WrongKey => write!(f, "response signed with another key"),
+            TsigError::BadSig => write!(f, "response signature does not verify"),
+            TsigError::BadTime => write!(f, "response signed at a bad time"),
+            TsigError::Malformed(e) => write!(f, "malformed signature: {}", e),
+        }
+    }
+}
+
+/// A shared key requests are signed with.
+#[derive(Clone)]
+pub struct Key {
+    name: Name,
+    algorithm: Algorithm,
+    secret: Vec<u8>,
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    /// Parse the first key of a BIND key file,
+    /// `key "name" { algorithm hmac-sha256; secret "base64"; };`.
+    fn from_str(file: &str) -> Result<Key, String> {
+        let file = file
+            .lines()
+            .map(strip_comment)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let statement = file
+            .trim_start()
+            .strip_prefix("key")
+            .ok_or("Expected a key statement")?;
+        let open = statement.find('{').ok_or("Expected { after the key name")?;
+        let close = statement[open..]
+            .find('}')
+            .map(|close| open + close)
+            .ok_or("Expected } closing the key")?;
+        let name = statement[..open].trim().trim_matches('"');
+        let name = Name::from_str(name)
+            .map(|name| to_fqdn(&name))
+            .map_err(|e| format!("Invalid key name {}: {}", name, e))?;
+        let (mut algorithm, mut secret) = (None, None);
+        for clause in statement[open + 1..close].split(';') {
+            let mut words = clause.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("algorithm"), Some(value)) => algorithm = Some(value.parse()?),
+                (Some("secret"), Some(value)) => {
+                    let value = value.trim_matches('"');
+                    secret = Some(
+                        base64::decode(value)
+                            .map_err(|e| format!("Invalid secret for {}: {}", name, e))?,
+                    )
+                }
+                _ => {}
+            }
+        }
+        Ok(Key {
+            algorithm: algorithm.ok_or(format!("No algorithm for key {}", name))?,
+            secret: secret.ok_or(format!("No secret for key {}", name))?,
+            name,
+        })
+    }
+}
+
+/// A line of a BIND configuration file without its `#` or `//` comment.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            '/' if !quoted && chars.peek().map(|(_, c)| *c) == Some('/') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+impl Key {
+    pub fn from_file(path: &str) -> Result<Key, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read the TSIG key {}: {}", path, e))?
+            .parse()
+            .map_err(|e| format!("Invalid TSIG key {}: {}", path, e))
+    }
+
+    /// Encode `request` with a TSIG record appended, and the verifier
+    /// its responses are checked with.
+    pub fn sign(&self, request: &Message) -> Result<(Vec<u8>, Verifier), String> {
+        self.sign_at(request, now())
+    }
+
+    fn sign_at(&self, request: &Message, time: u64) -> Result<(Vec<u8>, Verifier), String> {
+        let mut bytes = request
+            .to_vec()
+            .map_err(|e| format!("Failed to encode the request: {}", e.kind()))?;
+        let mut data = bytes.clone();
+        data.extend(self.variables(time, FUDGE, 0, &[]));
+        let mac = self.mac(&data);
+        bytes.extend(self.record(request.id(), time, &mac));
+        add_additional(&mut bytes, 1);
+        let verifier = Verifier {
+            key: self.clone(),
+            mac,
+            signed: false,
+            unsigned: vec![],
+            unsigned_count: 0,
+        };
+        Ok((bytes, verifier))
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            Algorithm::HmacMd5 => compute_mac::<Hmac<Md5>>(&self.secret, data),
+            Algorithm::HmacSha1 => compute_mac::<Hmac<Sha1>>(&self.secret, data),
+            Algorithm::HmacSha224 => compute_mac::<Hmac<Sha224>>(&self.secret, data),
+            Algorithm::HmacSha256 => compute_mac::<Hmac<Sha256>>(&self.secret, data),
+            Algorithm::HmacSha384 => compute_mac::<Hmac<Sha384>>(&self.secret, data),
+            Algorithm::HmacSha512 => compute_mac::<Hmac<Sha512>>(&self.secret, data),
+        }
+    }
+
+    /// The TSIG variables a first message is signed over along with it.
+    fn variables(&self, time: u64, fudge: u16, error: u16, other: &[u8]) -> Vec<u8> {
+        let mut variables = name_wire(&self.name);
+        variables.extend(&ANY.to_be_bytes());
+        variables.extend(&0u32.to_be_bytes());
+        variables.extend(name_wire(&self.algorithm.name()));
+        variables.extend(timers(time, fudge));
+        variables.extend(&error.to_be_bytes());
+        variables.extend(&(other.len() as u16).to_be_bytes());
+        variables.extend(other);
+        variables
+    }
+
+    /// The TSIG record carrying `mac` for a message with `id`.
+    fn record(&self, id: u16, time: u64, mac: &[u8]) -> Vec<u8> {
+        let mut rdata = name_wire(&self.algorithm.name());
+        rdata.extend(timers(time, FUDGE));
+        rdata.extend(&(mac.len() as u16).to_be_bytes());
+        rdata.extend(mac);
+        rdata.extend(&id.to_be_bytes());
+        rdata.extend(&0u16.to_be_bytes());
+        rdata.extend(&0u16.to_be_bytes());
+        let mut record = name_wire(&self.name);
+        record.extend(&TSIG.to_be_bytes());
+        record.extend(&ANY.to_be_bytes());
+        record.extend(&0u32.to_be_bytes());
+        record.extend(&(rdata.len() as u16).to_be_bytes());
+        record.extend(rdata);
+        record
+    }
+}
+
+/// Checks the signatures on the responses to a signed request, in the
+/// order they arrive.
+///
+/// The first response is signed over the request's MAC, and each later
+/// one over the MAC before it and the messages a server may leave
+/// unsigned in between, as zone transfers do.
+pub struct Verifier {
+    key: Key,
+    mac: Vec<u8>,
+    signed: bool,
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl Verifier {
+    /// Check the signature on the next response.
+    pub fn verify(&mut self, response: &[u8]) -> Result<(), TsigError> {
+        self.verify_at(response, now())
+    }
+
+    /// Check the last response was signed, a server may only leave the
+    /// messages before it unsigned.
+    pub fn finish(&self) -> Result<(), TsigError> {
+        if self.signed && self.unsigned_count == 0 {
+            Ok(())
+        } else {
+            Err(TsigError::Unsigned)
+        }
+    }
+
+    fn verify_at(&mut self, response: &[u8], now: u64) -> Result<(), TsigError> {
+        let signature = match Signature::split(response)? {
+            Some(signature) => signature,
+            None if self.signed && self.unsigned_count < MAX_UNSIGNED => {
+                self.unsigned.extend(response);
+                self.unsigned_count += 1;
+                return Ok(());
+            }
+            None => return Err(TsigError::Unsigned),
+        };
+        if signature.error != 0 {
+            return Err(TsigError::Rejected(signature.error));
+        }
+        if signature.key != self.key.name || signature.algorithm != self.key.algorithm.name() {
+            return Err(TsigError::WrongKey);
+        }
+        let mut data = (self.mac.len() as u16).to_be_bytes().to_vec();
+        data.extend(&self.mac);
+        data.extend(&self.unsigned);
+        data.extend(&signature.message);
+        if self.signed {
+            data.extend(timers(signature.time, signature.fudge));
+        } else {
+            data.extend(self.key.variables(
+                signature.time,
+                signature.fudge,
+                signature.error,
+                &signature.other,
+            ));
+        }
+        if self.key.mac(&data) != signature.mac {
+            return Err(TsigError::BadSig);
+        }
+        if now.max(signature.time) - now.min(signature.time) > u64::from(signature.fudge) {
+            return Err(TsigError::BadTime);
+        }
+        self.mac = signature.mac;
+        self.signed = true;
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+        Ok(())
+    }
+}
+
+/// The TSIG record of a message, and the message it signs.
+struct Signature {
+    /// The message without its TSIG record, under its original ID.
+    message: Vec<u8>,
+    key: Name,
+    algorithm: Name,
+    time: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Signature {
+    /// Split the TSIG record off the end of `message`, None when it has
+    /// none.
+    fn split(message: &[u8]) -> Result<Option<Signature>, TsigError> {
+        Signature::read(message).map_err(|e| TsigError::Malformed(e.kind().to_string()))
+    }
+
+    fn read(message: &[u8]) -> Result<Option<Signature>, ProtoError> {
+        let mut decoder = BinDecoder::new(message);
+        let header = decoder.read_slice(12)?.unverified();
+        let count = |at: usize| u16::from_be_bytes([header[at], header[at + 1]]) as usize;
+        let (queries, additionals) = (count(4), count(10));
+        if additionals == 0 {
+            return Ok(None);
+        }
+        for _ in 0..queries {
+            Name::read(&mut decoder)?;
+            decoder.read_slice(4)?;
+        }
+        for _ in 0..count(6) + count(8) + additionals - 1 {
+            skip_record(&mut decoder)?;
+        }
+        let start = decoder.index();
+        let key = Name::read(&mut decoder)?;
+        if decoder.read_u16()?.unverified() != TSIG {
+            return Ok(None);
+        }
+        decoder.read_slice(8)?;
+        let algorithm = Name::read(&mut decoder)?;
+        let time = (u64::from(decoder.read_u16()?.unverified()) << 32)
+            | u64::from(decoder.read_u32()?.unverified());
+        let fudge = decoder.read_u16()?.unverified();
+        let mac_len = decoder.read_u16()?.unverified() as usize;
+        let mac = decoder.read_vec(mac_len)?.unverified();
+        let id = decoder.read_u16()?.unverified();
+        let error = decoder.read_u16()?.unverified();
+        let other_len = decoder.read_u16()?.unverified() as usize;
+        let other = decoder.read_vec(other_len)?.unverified();
+        let mut unsigned = message[..start].to_vec();
+        unsigned[..2].copy_from_slice(&id.to_be_bytes());
+        add_additional(&mut unsigned, -1);
+        Ok(Some(Signature {
+            message: unsigned,
+            key,
+            algorithm,
+            time,
+            fudge,
+            mac,
+            error,
+            other,
+        }))
+    }
+}
+
+fn skip_record(decoder: &mut BinDecoder<'_>) -> Result<(), ProtoError> {
+    Name::read(decoder)?;
+    decoder.read_slice(8)?;
+    let len = decoder.read_u16()?.unverified() as usize;
+    decoder.read_slice(len)?;
+    Ok(())
+}
+
+/// Change the additional count in the header of `message` by `by`.
+fn add_additional(message: &mut [u8], by: i32) {
+    let count = i32::from(u16::from_be_bytes([message[10], message[11]])) + by;
+    message[10..12].copy_from_slice(&(count as u16).to_be_bytes());
+}
+
+fn compute_mac<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// A name in canonical wire format, lowercase and uncompressed.
+fn name_wire(name: &Name) -> Vec<u8> {
+    let mut wire = vec![];
+    for label in name.iter() {
+        wire.push(label.len() as u8);
+        wire.extend(label.to_ascii_lowercase());
+    }
+    wire.push(0);
+    wire
+}
+
+/// The 48 bit time signed at and the fudge.
+fn timers(time: u64, fudge: u16) -> Vec<u8> {
+    let mut timers = time.to_be_bytes()[2..].to_vec();
+    timers.extend(&fudge.to_be_bytes());
+    timers
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_proto::op::{MessageType, OpCode, Query, ResponseCode};
+    use trust_dns_proto::rr::{RData, Record, RecordType};
+
+    const KEY_FILE: &str = r#"
+key "transfer.example.com" {
+	algorithm hmac-sha256;
+	secret "c2VjcmV0IHNoYXJlZCB3aXRoIHRoZSBzZXJ2ZXI=";
+};
+"#;
+
+    fn request() -> Message {
+        let mut request = Message::new();
+        request
+            .set_id(4321)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .add_query(Query::query(
+                Name::from_str("example.com.").unwrap(),
+                RecordType::AXFR,
+            ));
+        request
+    }
+
+    /// Sign `response` the way a server answering the request signed with
+    /// `request_mac` would, with an empty MAC when `error` is set.
+    fn sign_response(
+        key: &Key,
+        request_mac: &[u8],
+        response: &Message,
+        time: u64,
+        error: u16,
+    ) -> Vec<u8> {
+        let mut bytes = response.to_vec().unwrap();
+        let mut data = (request_mac.len() as u16).to_be_bytes().to_vec();
+        data.extend(request_mac);
+        data.extend(&bytes);
+        data.extend(key.variables(time, FUDGE, error, &[]));
+        let mac = if error == 0 { key.mac(&data) } else { vec![] };
+        let mut record = key.record(response.id(), time, &mac);
+        let error_at = record.len() - 4;
+        record[error_at..error_at + 2].copy_from_slice(&error.to_be_bytes());
+        bytes.extend(record);
+        add_additional(&mut bytes, 1);
+        bytes
+    }
+
+    fn response(request: &Message) -> Message {
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_response_code(ResponseCode::NoError)
+            .add_query(request.queries()[0].clone())
+            .add_answer(Record::from_rdata(
+                Name::from_str("www.example.com.").unwrap(),
+                60,
+                RData::A("10.0.0.1".parse().unwrap()),
+            ));
+        response
+    }
+
+    #[test]
+    fn test_key_from_bind_file() {
+        let key = KEY_FILE.parse::<Key>().unwrap();
+        assert_eq!(key.name, Name::from_str("transfer.example.com.").unwrap());
+        assert_eq!(key.algorithm, Algorithm::HmacSha256);
+        assert_eq!(key.secret, b"secret shared with the server".to_vec());
+        assert!("key \"k\" { algorithm hmac-sha3; secret \"AA==\"; };"
+            .parse::<Key>()
+            .is_err());
+        assert!("key \"k\" { algorithm hmac-sha1; };"
+            .parse::<Key>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_strip_comment() {
+        assert_eq!(
+            strip_comment("secret \"ab//cd==\"; // key"),
+            "secret \"ab//cd==\"; "
+        );
+        assert_eq!(strip_comment("# generated"), "");
+    }
+
+    #[test]
+    fn test_signed_request_carries_tsig_record() {
+        let key = KEY_FILE.parse::<Key>().unwrap();
+        let (bytes, verifier) = key.sign_at(&request(), 1_600_000_000).unwrap();
+        let signature = Signature::split(&bytes).unwrap().unwrap();
+        assert_eq!(signature.message, request().to_vec().unwrap());
+        assert_eq!(signature.key, key.name);
+        assert_eq!(signature.time, 1_600_000_000);
+        assert_eq!(signature.mac, verifier.mac);
+        assert_eq!(signature.mac.len(), 32);
+        // The request still decodes, the TSIG record as an unknown record.
+        assert_eq!(Message::from_vec(&bytes).unwrap().additionals().len(), 1);
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let key = KEY_FILE.parse::<Key>().unwrap();
+        let now = 1_600_000_000;
+        let (_, verifier) = key.sign_at(&request(), now).unwrap();
+        let response = response(&request());
+
+        let signed = sign_response(&key, &verifier.mac, &response, now, 0);
+        assert_eq!(verifier_for(&key, now).verify_at(&signed, now), Ok(()));
+
+        let mut tampered = signed.clone();
+        tampered[14] ^= 1;
+        assert_eq!(
+            verifier_for(&key, now).verify_at(&tampered, now),
+            Err(TsigError::BadSig)
+        );
+
+        assert_eq!(
+            verifier_for(&key, now).verify_at(&signed, now + 1000),
+            Err(TsigError::BadTime)
+        );
+        assert_eq!(
+            verifier_for(&key, now).verify_at(&response.to_vec().unwrap(), now),
+            Err(TsigError::Unsigned)
+        );
+        let rejected = sign_response(&key, &verifier.mac, &response, now, 17);
+        assert_eq!(
+            verifier_for(&key, now).verify_at(&rejected, now),
+            Err(TsigError::Rejected(17))
+        );
+        let other = "key \"other\" { algorithm hmac-sha256; secret \"AA==\"; };"
+            .parse::<Key>()
+            .unwrap();
+        let wrong = sign_response(&other, &verifier.mac, &response, now, 0);
+        assert_eq!(
+            verifier_for(&key, now).verify_at(&wrong, now),
+            Err(TsigError::WrongKey)
+        );
+    }
+
+    #[test]
+    fn test_verify_unsigned_messages_in_between() {
+        let key = KEY_FILE.parse::<Key>().unwrap();
+        let now = 1_600_000_000;
+        let mut verifier = verifier_for(&key, now);
+        let response = response(&request());
+        let first = sign_response(&key, &verifier.mac, &response, now, 0);
+        let first_mac = Signature::split(&first).unwrap().unwrap().mac;
+        let unsigned = response.to_vec().unwrap();
+        // Later messages are signed over the MAC before them, the unsigned
+        // messages since and their timers only.
+        let mut last = response.to_vec().unwrap();
+        let mut data = (first_mac.len() as u16).to_be_bytes().to_vec();
+        data.extend(&first_mac);
+        data.extend(&unsigned);
+        data.extend(&last);
+        data.extend(timers(now, FUDGE));
+        last.extend(key.record(response.id(), now, &key.mac(&data)));
+        add_additional(&mut last, 1);
+
+        assert_eq!(verifier.verify_at(&first, now), Ok(()));
+        assert_eq!(verifier.verify_at(&unsigned, now), Ok(()));
+        assert_eq!(verifier.finish(), Err(TsigError::Unsigned));
+        assert_eq!(verifier.verify_at(&last, now), Ok(()));
+        assert_eq!(verifier.finish(), Ok(()));
+    }
+
+    fn verifier_for(key: &Key, now: u64) -> Verifier {
+        key.sign_at(&request(), now).unwrap().1
+    }
+}
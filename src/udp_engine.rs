@@ -0,0 +1,377 @@
+use crate::transport::Error;
+use futures::channel::oneshot;
+use futures::future::{AbortHandle, Abortable};
+use futures::lock::Mutex as AsyncMutex;
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::udp::{RecvHalf, SendHalf};
+use tokio::net::UdpSocket;
+use trust_dns_proto::op::{Message, MessageType, Query};
+
+/// The number of sockets queries are spread over.
+const SOCKETS: usize = 8;
+
+/// The largest datagram read from a socket, the largest payload EDNS can
+/// advertise.
+const MAX_DATAGRAM: usize = u16::MAX as usize;
+
+/// The pause in reading a socket after an error, doubled for each error
+/// in a row.
+const MIN_BACKOFF: Duration = Duration::from_millis(1);
+
+/// The longest pause in reading a socket after errors.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+type Pending = HashMap<u16, (Query, SocketAddr, oneshot::Sender<Message>)>;
+
+/// A pool of UDP sockets DNS queries are written straight onto, in the
+/// style of massdns, for the transport to send its UDP queries over.
+///
+/// There is a pool for each address family among the servers. Each socket
+/// has a task reading its responses, which are matched to the waiting
+/// query by message ID, question and server address. Anything that does
+/// not match, including late answers to queries that already timed out,
+/// is dropped. The readers stop once the last clone of the engine is
+/// dropped.
+#[derive(Clone)]
+pub struct UdpEngine {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    v4: Vec<Socket>,
+    v6: Vec<Socket>,
+    next_socket: AtomicUsize,
+}
+
+struct Socket {
+    /// Sends are serialised, a socket only wakes one waiting writer.
+    send: AsyncMutex<SendHalf>,
+    pending: Arc<Mutex<Pending>>,
+    reader: AbortHandle,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl UdpEngine {
+    /// Bind a socket pool able to reach `servers` and start reading
+    /// their responses.
+    pub async fn new(servers: &[SocketAddr]) -> std::io::Result<UdpEngine> {
+        if servers.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "No name servers for the UDP engine",
+            ));
+        }
+        let v4 = if servers.iter().any(SocketAddr::is_ipv4) {
+            bind_pool(IpAddr::V4(Ipv4Addr::UNSPECIFIED)).await?
+        } else {
+            vec![]
+        };
+        let v6 = if servers.iter().any(SocketAddr::is_ipv6) {
+            bind_pool(IpAddr::V6(Ipv6Addr::UNSPECIFIED)).await?
+        } else {
+            vec![]
+        };
+        Ok(UdpEngine {
+            inner: Arc::new(Inner {
+                v4,
+                v6,
+                next_socket: AtomicUsize::new(0),
+            }),
+        })
+    }
+
+    /// Send `request` to `server` under an ID of the pool's choosing and
+    /// wait up to `wait` for its response.
+    pub async fn query(
+        &self,
+        mut request: Message,
+        server: SocketAddr,
+        wait: Duration,
+    ) -> Result<Message, Error> {
+        let sockets = if server.is_ipv6() {
+            &self.inner.v6
+        } else {
+            &self.inner.v4
+        };
+        if sockets.is_empty() {
+            let error = format!("no socket of the address family of {}", server);
+            return Err(Error::Failed(error));
+        }
+        let query = request
+            .queries()
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::Failed("query without a question".to_string()))?;
+        let socket =
+            &sockets[self.inner.next_socket.fetch_add(1, Ordering::Relaxed) % sockets.len()];
+        let (sender, receiver) = oneshot::channel();
+        let id = {
+            let mut pending = socket
+                .pending
+                .lock()
+                .expect("pending queries lock poisoned");
+            let id = free_id(&pending).ok_or_else(|| {
+                Error::Failed(format!(
+                    "every query ID is in use on the socket to {}",
+                    server
+                ))
+            })?;
+            pending.insert(id, (query, server, sender));
+            id
+        };
+        request.set_id(id);
+        let result = send_and_wait(&socket.send, &request, server, wait, receiver).await;
+        if result.is_err() {
+            socket
+                .pending
+                .lock()
+                .expect("pending queries lock poisoned")
+                .remove(&id);
+        }
+        result
+    }
+}
+
+/// A random ID no pending query has, None when they all are taken.
+fn free_id(pending: &Pending) -> Option<u16> {
+    let start = thread_rng().gen::<u16>();
+    (0..=u16::MAX)
+        .map(|offset| start.wrapping_add(offset))
+        .find(|id| !pending.contains_key(id))
+}
+
+/// Bind `SOCKETS` sockets on `address` and start reading their responses.
+async fn bind_pool(address: IpAddr) -> std::io::Result<Vec<Socket>> {
+    let mut sockets = vec![];
+    for _ in 0..SOCKETS {
+        let socket = UdpSocket::bind(SocketAddr::new(address, 0)).await?;
+        let (recv, send) = socket.split();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let (reader, registration) = AbortHandle::new_pair();
+        tokio::spawn(Abortable::new(receive(recv, pending.clone()), registration));
+        sockets.push(Socket {
+            send: AsyncMutex::new(send),
+            pending,
+            reader,
+        });
+    }
+    Ok(sockets)
+}
+
+async fn send_and_wait(
+    socket: &AsyncMutex<SendHalf>,
+    request: &Message,
+    server: SocketAddr,
+    wait: Duration,
+    receiver: oneshot::Receiver<Message>,
+) -> Result<Message, Error> {
+    let bytes = request
+        .to_vec()
+        .map_err(|e| Error::Failed(format!("failed to encode the query: {}", e.kind())))?;
+    socket
+        .lock()
+        .await
+        .send_to(&bytes, &server)
+        .await
+        .map_err(|e| Error::Failed(format!("failed to send the query to {}: {}", server, e)))?;
+    match tokio::time::timeout(wait, receiver).await {
+        Ok(Ok(message)) => Ok(message),
+        Ok(Err(_)) => Err(Error::Failed(format!("query to {} was cancelled", server))),
+        Err(_) => Err(Error::Timeout),
+    }
+}
+
+/// Read the responses arriving on `socket` and hand each one to the query
+/// waiting for it.
+async fn receive(mut socket: RecvHalf, pending: Arc<Mutex<Pending>>) {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    let mut backoff = Duration::default();
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => {
+                backoff = Duration::default();
+                received
+            }
+            // Errors such as ICMP unreachables come and go, one that
+            // persists is not retried in a tight loop.
+            Err(_) => {
+                backoff = (backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF);
+                tokio::time::delay_for(backoff).await;
+                continue;
+            }
+        };
+        let message = match Message::from_vec(&buf[..len]) {
+            Ok(message) if message.message_type() == MessageType::Response => message,
+            _ => continue,
+        };
+        let mut pending = pending.lock().expect("pending queries lock poisoned");
+        let matches = pending
+            .get(&message.id())
+            .is_some_and(|(query, server, _)| {
+                *server == from
+                    && message.queries().len() == 1
+                    && message.queries()[0].name() == query.name()
+                    && message.queries()[0].query_type() == query.query_type()
+            });
+        if matches {
+            if let Some((_, _, sender)) = pending.remove(&message.id()) {
+                let _ = sender.send(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::transport::request;
+    use std::str::FromStr;
+    use trust_dns_proto::op::{OpCode, ResponseCode};
+    use trust_dns_proto::rr::{Name, RecordType};
+    use trust_dns_proto::rr::{RData, Record};
+
+    /// Serve canned answers on a local socket: names starting with `nx`
+    /// do not exist, names starting with `fail` get SERVFAIL, names
+    /// starting with `drop` are never answered, names
+    /// starting with `to-` are CNAMEs of their parent, and every other
+    /// name resolves to 10.0.0.1.
+    pub async fn stand_in_server() -> SocketAddr {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; MAX_DATAGRAM];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let label =
+                    String::from_utf8_lossy(query.name().iter().next().unwrap()).to_string();
+                if label.starts_with("drop") {
+                    continue;
+                }
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .add_query(query.clone());
+                if label.starts_with("nx") {
+                    response.set_response_code(ResponseCode::NXDomain);
+                } else if label.starts_with("fail") {
+                    response.set_response_code(ResponseCode::ServFail);
+                } else if label.starts_with("to-") && query.query_type() == RecordType::CNAME {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::CNAME(query.name().base_name()),
+                    ));
+                } else if query.query_type() == RecordType::A {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::A("10.0.0.1".parse().unwrap()),
+                    ));
+                }
+                let bytes = response.to_vec().unwrap();
+                socket.send_to(&bytes, &from).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    /// How long the tests wait for an answer.
+    const WAIT: Duration = Duration::from_secs(2);
+
+    #[tokio::test]
+    async fn test_udp_engine_resolves() {
+        let server = stand_in_server().await;
+        let engine = UdpEngine::new(&[server]).await.unwrap();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let response = engine
+            .query(request(query, true), server, WAIT)
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].name(), &name);
+    }
+
+    #[tokio::test]
+    async fn test_udp_engine_nxdomain() {
+        let server = stand_in_server().await;
+        let engine = UdpEngine::new(&[server]).await.unwrap();
+        let query = Query::query(Name::from_str("nx.example.com.").unwrap(), RecordType::A);
+        let response = engine
+            .query(request(query, true), server, WAIT)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[tokio::test]
+    async fn test_udp_engine_mixed_address_families() {
+        let server = stand_in_server().await;
+        let v6 = SocketAddr::from_str("[2001:db8::53]:53").unwrap();
+        let engine = UdpEngine::new(&[server, v6]).await.unwrap();
+        assert_eq!(engine.inner.v4.len(), SOCKETS);
+        assert_eq!(engine.inner.v6.len(), SOCKETS);
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let response = engine
+            .query(request(query, true), server, WAIT)
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[test]
+    fn test_free_id() {
+        let query = Query::query(Name::from_str("example.com.").unwrap(), RecordType::A);
+        let server = SocketAddr::from_str("127.0.0.1:53").unwrap();
+        let mut pending = Pending::new();
+        for id in 0..=u16::MAX {
+            pending.insert(id, (query.clone(), server, oneshot::channel().0));
+        }
+        assert_eq!(free_id(&pending), None);
+        pending.remove(&7);
+        assert_eq!(free_id(&pending), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_udp_engine_many_concurrent_queries() {
+        use futures::prelude::*;
+        let server = stand_in_server().await;
+        let engine = UdpEngine::new(&[server]).await.unwrap();
+        let answered = futures::stream::iter(0..500)
+            .map(|i| {
+                let name = Name::from_str(&format!("host{}.example.com.", i)).unwrap();
+                let engine = engine.clone();
+                async move {
+                    let query = Query::query(name.clone(), RecordType::A);
+                    engine
+                        .query(request(query, true), server, WAIT)
+                        .await
+                        .map(|m| (name, m))
+                }
+            })
+            .buffer_unordered(100)
+            .filter(|r| {
+                future::ready(
+                    r.as_ref()
+                        .is_ok_and(|(name, m)| m.answers()[0].name() == name),
+                )
+            })
+            .count()
+            .await;
+        assert_eq!(answered, 500);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::engine::Engine;
+use crate::progress;
+use crate::transport::{request, Transport};
+use crate::wildcard::{random_label, to_fqdn};
+use colored::*;
+use futures::future::join_all;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use trust_dns_proto::op::{Message, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RData, RecordType};
+
+/// A zone without a wildcard, random names under it must not exist.
+const NXDOMAIN_ZONE: &str = "example.com.";
+
+/// The number of nonexistent names each resolver is asked for.
+const NXDOMAIN_PROBES: usize = 2;
+
+/// A name and an address it is known to resolve to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownAnswer {
+    name: Name,
+    ip: IpAddr,
+}
+
+impl FromStr for KnownAnswer {
+    type Err = String;
+
+    /// Parse `name=ip`.
+    fn from_str(known_answer: &str) -> Result<KnownAnswer, String> {
+        let mut parts = known_answer.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let ip = parts
+            .next()
+            .ok_or(format!("Known answer must be name=ip {}", known_answer))?;
+        Ok(KnownAnswer {
+            name: Name::from_str(name)
+                .map(|name| to_fqdn(&name))
+                .map_err(|e| format!("Invalid name in known answer {}: {}", known_answer, e))?,
+            ip: ip
+                .parse()
+                .map_err(|e| format!("Invalid address in known answer {}: {}", known_answer, e))?,
+        })
+    }
+}
+
+/// The checks a resolver has to pass before it is used.
+pub struct Checks {
+    /// The domain enumerated, its name servers are compared with the
+    /// trusted resolver's.
+    pub domain: Name,
+    pub port: u16,
+    pub known_answers: Vec<KnownAnswer>,
+    /// How the resolvers are queried.
+    pub transport: Transport,
+    /// The resolver whose answers are taken as the truth.
+    pub trusted: Engine,
+    /// Keep resolvers failing the checks, only flagging them.
+    pub keep_untrusted: bool,
+}
+
+/// Check the resolvers at `ips` for hijacked NXDOMAIN answers, wrong
+/// known answers and name servers that differ from the trusted
+/// resolver's, returning the resolvers that can be used.
+pub async fn vet_resolvers(ips: Vec<IpAddr>, checks: &Checks) -> Vec<IpAddr> {
+    let addrs = ips
+        .iter()
+        .map(|ip| SocketAddr::new(*ip, checks.port))
+        .collect::<Vec<_>>();
+    let trusted_ns = trusted_name_servers(checks).await;
+    let problems = join_all(
+        addrs
+            .iter()
+            .map(|addr| check_resolver(*addr, checks, &trusted_ns)),
+    )
+    .await;
+    let mut vetted = vec![];
+    for (ip, problems) in ips.into_iter().zip(problems) {
+        if problems.is_empty() {
+            vetted.push(ip);
+            continue;
+        }
+        let action = if checks.keep_untrusted {
+            vetted.push(ip);
+            "Keeping untrusted"
+        } else {
+            "Removing"
+        };
+        progress::note(format!(
+            "[{}] {} name server {}: {}",
+            "WRN".yellow(),
+            action,
+            ip,
+            problems.join(", ")
+        ));
+    }
+    vetted
+}
+
+/// The name servers of the domain according to the trusted resolver.
+async fn trusted_name_servers(checks: &Checks) -> HashSet<Name> {
+    match checks
+        .trusted
+        .lookup(checks.domain.clone(), RecordType::NS)
+        .await
+    {
+        Ok(lookup) => lookup
+            .record_iter()
+            .filter_map(|record| match record.rdata() {
+                RData::NS(name) => Some(to_fqdn(name)),
+                _ => None,
+            })
+            .collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// Run every check against the resolver at `addr`, describing each one
+/// it failed.
+async fn check_resolver(
+    addr: SocketAddr,
+    checks: &Checks,
+    trusted_ns: &HashSet<Name>,
+) -> Vec<String> {
+    let ask = |name: &Name, record_type| {
+        let query = Query::query(name.clone(), record_type);
+        async move {
+            let name_server = addr.into();
+            checks
+                .transport
+                .query(&name_server, request(query, true), None)
+                .await
+        }
+    };
+    let mut problems = vec![];
+    let zone = Name::from_str(NXDOMAIN_ZONE).expect("invalid NXDOMAIN zone");
+    for _ in 0..NXDOMAIN_PROBES {
+        let name = match random_label() {
+            Ok(label) => to_fqdn(&label.append_domain(&zone)),
+            Err(_) => continue,
+        };
+        match ask(&name, RecordType::A).await {
+            Ok(response) => problems.extend(check_nxdomain(&name, &response)),
+            Err(e) => return vec![e.to_string()],
+        }
+    }
+    let mut names = checks
+        .known_answers
+        .iter()
+        .map(|known| &known.name)
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let expected = checks
+            .known_answers
+            .iter()
+            .filter(|known| &known.name == name)
+            .map(|known| known.ip)
+            .collect::<HashSet<_>>();
+        match ask(name, RecordType::A).await {
+            Ok(response) => problems.extend(check_known_answer(name, &expected, &response)),
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    if !trusted_ns.is_empty() {
+        match ask(&checks.domain, RecordType::NS).await {
+            Ok(response) => {
+                problems.extend(check_name_servers(&checks.domain, trusted_ns, &response))
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    problems
+}
+
+fn addresses(response: &Message) -> Vec<IpAddr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.rdata() {
+            RData::A(ip) => Some(IpAddr::V4(*ip)),
+            RData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn display_ips(ips: &[IpAddr]) -> String {
+    let mut ips = ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+    ips.sort();
+    format!("[{}]", ips.join(","))
+}
+
+fn display_names<'a>(names: impl IntoIterator<Item = &'a Name>) -> String {
+    let mut names = names
+        .into_iter()
+        .map(|name| name.to_ascii())
+        .collect::<Vec<_>>();
+    names.sort();
+    format!("[{}]", names.join(","))
+}
+
+/// A nonexistent name has to be answered with NXDOMAIN.
+fn check_nxdomain(name: &Name, response: &Message) -> Option<String> {
+    match response.response_code() {
+        ResponseCode::NXDomain => None,
+        ResponseCode::NoError if !addresses(response).is_empty() => Some(format!(
+            "answered {} for the nonexistent name {}",
+            display_ips(&addresses(response)),
+            name.to_ascii()
+        )),
+        code => Some(format!(
+            "answered {} for the nonexistent name {}",
+            code,
+            name.to_ascii()
+        )),
+    }
+}
+
+/// A known name has to resolve to at least one of its known addresses.
+fn check_known_answer(
+    name: &Name,
+    expected: &HashSet<IpAddr>,
+    response: &Message,
+) -> Option<String> {
+    let ips = addresses(response);
+    if ips.iter().any(|ip| expected.contains(ip)) {
+        return None;
+    }
+    let mut expected = expected.iter().copied().collect::<Vec<_>>();
+    expected.sort();
+    Some(format!(
+        "answered {} for {}, expected {}",
+        display_ips(&ips),
+        name.to_ascii(),
+        display_ips(&expected)
+    ))
+}
+
+/// The name servers of the domain have to be those the trusted resolver
+/// gives.
+fn check_name_servers(
+    domain: &Name,
+    trusted: &HashSet<Name>,
+    response: &Message,
+) -> Option<String> {
+    let name_servers = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.rdata() {
+            RData::NS(name) => Some(to_fqdn(name)),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    if &name_servers == trusted {
+        return None;
+    }
+    Some(format!(
+        "answered name servers {} for {}, the trusted resolver answered {}",
+        display_names(&name_servers),
+        domain.to_ascii(),
+        display_names(trusted)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::transport;
+    use crate::pool::Limits;
+    use crate::rate::RateController;
+    use crate::udp_engine::tests::stand_in_server;
+    use std::sync::Arc;
+    use trust_dns_proto::rr::Record;
+    use trust_dns_resolver::config::NameServerConfigGroup;
+
+    fn name(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn response(code: ResponseCode, rdatas: Vec<RData>) -> Message {
+        let mut message = Message::new();
+        message.set_response_code(code);
+        for rdata in rdatas {
+            message.add_answer(Record::from_rdata(name("www.example.com."), 60, rdata));
+        }
+        message
+    }
+
+    #[test]
+    fn test_known_answer_from_str() {
+        let known = "www.example.com=93.184.216.34"
+            .parse::<KnownAnswer>()
+            .unwrap();
+        assert_eq!(known.name, name("www.example.com."));
+        assert_eq!(known.ip, "93.184.216.34".parse::<IpAddr>().unwrap());
+        assert!("www.example.com".parse::<KnownAnswer>().is_err());
+        assert!("www.example.com=nope".parse::<KnownAnswer>().is_err());
+    }
+
+    #[test]
+    fn test_check_nxdomain() {
+        let probe = name("x1y2.example.com.");
+        assert_eq!(
+            check_nxdomain(&probe, &response(ResponseCode::NXDomain, vec![])),
+            None
+        );
+        let hijacked = response(
+            ResponseCode::NoError,
+            vec![RData::A("10.0.0.1".parse().unwrap())],
+        );
+        assert_eq!(
+            check_nxdomain(&probe, &hijacked).unwrap(),
+            "answered [10.0.0.1] for the nonexistent name x1y2.example.com."
+        );
+        assert!(check_nxdomain(&probe, &response(ResponseCode::ServFail, vec![])).is_some());
+    }
+
+    #[test]
+    fn test_check_known_answer() {
+        let expected = vec!["10.0.0.1".parse().unwrap()].into_iter().collect();
+        let good = response(
+            ResponseCode::NoError,
+            vec![
+                RData::A("10.0.0.1".parse().unwrap()),
+                RData::A("10.0.0.2".parse().unwrap()),
+            ],
+        );
+        assert_eq!(
+            check_known_answer(&name("www.example.com."), &expected, &good),
+            None
+        );
+        let injected = response(
+            ResponseCode::NoError,
+            vec![RData::A("192.0.2.7".parse().unwrap())],
+        );
+        assert_eq!(
+            check_known_answer(&name("www.example.com."), &expected, &injected).unwrap(),
+            "answered [192.0.2.7] for www.example.com., expected [10.0.0.1]"
+        );
+    }
+
+    #[test]
+    fn test_check_name_servers() {
+        let trusted = vec![name("a.iana-servers.net."), name("b.iana-servers.net.")]
+            .into_iter()
+            .collect();
+        let same = response(
+            ResponseCode::NoError,
+            vec![
+                RData::NS(name("b.iana-servers.net.")),
+                RData::NS(name("a.iana-servers.net.")),
+            ],
+        );
+        assert_eq!(
+            check_name_servers(&name("example.com."), &trusted, &same),
+            None
+        );
+        let stale = response(
+            ResponseCode::NoError,
+            vec![RData::NS(name("ns1.old-host.net."))],
+        );
+        assert!(check_name_servers(&name("example.com."), &trusted, &stale).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_vet_resolvers_removes_nxdomain_hijacking() {
+        // The stand-in answers every name that does not start with nx.
+        let server = stand_in_server().await;
+        let trusted = Engine::recursive(
+            &NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port()),
+            transport(),
+            Limits::default(),
+            Arc::new(RateController::new(1_000_000, 1_000_000)),
+        );
+        let mut checks = Checks {
+            domain: name("example.com."),
+            port: server.port(),
+            known_answers: vec![],
+            transport: transport(),
+            trusted,
+            keep_untrusted: false,
+        };
+        assert!(vet_resolvers(vec![server.ip()], &checks).await.is_empty());
+        checks.keep_untrusted = true;
+        assert_eq!(
+            vet_resolvers(vec![server.ip()], &checks).await,
+            vec![server.ip()]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use crate::engine::Engine;
+use crate::progress;
+use futures::lock::Mutex;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+
+/// The number of random labels probed under each parent.
+const PROBE_LABELS: usize = 3;
+
+/// The record types requested for each random label.
+const PROBE_TYPES: [RecordType; 3] = [RecordType::A, RecordType::AAAA, RecordType::CNAME];
+
+/// The answers a wildcard record gives for names that do not exist.
+///
+/// Hits whose addresses all fall inside the wildcard's address set, or
+/// whose CNAME chain ends on the wildcard's target, are treated as
+/// wildcard answers rather than real subdomains.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WildcardFingerprint {
+    ips: HashSet<IpAddr>,
+    cnames: HashSet<Name>,
+    ttl: u32,
+}
+
+impl WildcardFingerprint {
+    pub fn from_records<'a>(records: impl IntoIterator<Item = &'a Record>) -> WildcardFingerprint {
+        let mut fingerprint = WildcardFingerprint::default();
+        for record in records {
+            match record.rdata() {
+                RData::A(ip) => {
+                    fingerprint.ips.insert(IpAddr::V4(*ip));
+                }
+                RData::AAAA(ip) => {
+                    fingerprint.ips.insert(IpAddr::V6(*ip));
+                }
+                RData::CNAME(name) => {
+                    fingerprint.cnames.insert(name.clone());
+                }
+                _ => continue,
+            }
+            fingerprint.ttl = fingerprint.ttl.max(record.ttl());
+        }
+        fingerprint
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.cnames.is_empty()
+    }
+
+    /// Check whether the records of a hit are the wildcard answer.
+    pub fn matches<'a>(&self, records: impl IntoIterator<Item = &'a Record>) -> bool {
+        let mut cname_match = false;
+        let mut ips = vec![];
+        for record in records {
+            match record.rdata() {
+                RData::A(ip) => ips.push(IpAddr::V4(*ip)),
+                RData::AAAA(ip) => ips.push(IpAddr::V6(*ip)),
+                RData::CNAME(name) => cname_match |= self.cnames.contains(name),
+                _ => (),
+            }
+        }
+        cname_match || (!ips.is_empty() && ips.iter().all(|ip| self.ips.contains(ip)))
+    }
+}
+
+impl std::fmt::Display for WildcardFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ips = self.ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+        ips.sort();
+        let mut cnames = self
+            .cnames
+            .iter()
+            .map(|name| name.to_ascii())
+            .collect::<Vec<_>>();
+        cnames.sort();
+        write!(
+            f,
+            "ips=[{}] cname=[{}] ttl={}",
+            ips.join(","),
+            cnames.join(","),
+            self.ttl
+        )
+    }
+}
+
+/// Wildcard fingerprints keyed by the parent name they were probed under.
+///
+/// A random label under a hit's parent gets exactly the answer of the
+/// closest enclosing wildcard, so each hit is judged against the
+/// fingerprint of its own parent, probed once and then cached.
+#[derive(Default)]
+pub struct WildcardCache {
+    fingerprints: Mutex<HashMap<Name, Slot>>,
+}
+
+/// The fingerprint of one parent, None until it is probed, behind a lock
+/// of its own so that only checks under the same parent wait for a probe.
+type Slot = Arc<Mutex<Option<Option<WildcardFingerprint>>>>;
+
+impl WildcardCache {
+    pub fn new() -> WildcardCache {
+        WildcardCache::default()
+    }
+
+    /// Probe `parent` for wildcard resolution, unless it has been probed already.
+    pub async fn probe(&self, parent: &Name, engine: &Engine) -> Option<WildcardFingerprint> {
+        let parent = to_fqdn(parent);
+        let slot = self
+            .fingerprints
+            .lock()
+            .await
+            .entry(parent.clone())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(fingerprint) = &*slot {
+            return fingerprint.clone();
+        }
+        let fingerprint = probe_wildcard(&parent, engine).await;
+        if let Some(fingerprint) = &fingerprint {
+            progress::note(format!(
+                "Wild card resolution is enabled on {}, answers matching it will be filtered: {}",
+                parent.to_ascii(),
+                fingerprint
+            ));
+        }
+        *slot = Some(fingerprint.clone());
+        fingerprint
+    }
+
+    /// Check whether the records found for `name` are the answer of the
+    /// closest enclosing wildcard.
+    pub async fn is_wildcard<'a>(
+        &self,
+        name: &Name,
+        records: impl IntoIterator<Item = &'a Record>,
+        engine: &Engine,
+    ) -> bool {
+        self.probe(&name.base_name(), engine)
+            .await
+            .is_some_and(|fingerprint| fingerprint.matches(records))
+    }
+}
+
+/// Check if `parent` is configured with wildcard resolution by requesting
+/// several random labels under it, for each of the probed record types.
+async fn probe_wildcard(parent: &Name, engine: &Engine) -> Option<WildcardFingerprint> {
+    let mut records = vec![];
+    for _ in 0..PROBE_LABELS {
+        let name = match random_label().map(|label| label.append_domain(parent)) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        for record_type in PROBE_TYPES.iter() {
+            if let Ok(lookup) = engine.lookup(name.clone(), *record_type).await {
+                records.extend(lookup.record_iter().cloned());
+            }
+        }
+    }
+    let fingerprint = WildcardFingerprint::from_records(&records);
+    if fingerprint.is_empty() {
+        None
+    } else {
+        Some(fingerprint)
+    }
+}
+
+pub fn random_label() -> trust_dns_proto::error::ProtoResult<Name> {
+    let label: String = thread_rng().sample_iter(&Alphanumeric).take(12).collect();
+    Name::from_ascii(label)
+}
+
+pub fn to_fqdn(name: &Name) -> Name {
+    let mut name = name.clone();
+    name.set_fqdn(true);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn a_record(name: &str, ip: &str, ttl: u32) -> Record {
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            ttl,
+            RData::A(ip.parse().unwrap()),
+        )
+    }
+
+    fn cname_record(name: &str, target: &str) -> Record {
+        Record::from_rdata(
+            Name::from_str(name).unwrap(),
+            300,
+            RData::CNAME(Name::from_str(target).unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_wildcard_fingerprint_matches_same_ips() {
+        let fingerprint = WildcardFingerprint::from_records(&[
+            a_record("x1.example.com", "10.0.0.1", 60),
+            a_record("x1.example.com", "10.0.0.2", 60),
+        ]);
+        assert!(fingerprint.matches(&[a_record("www.example.com", "10.0.0.2", 30)]));
+    }
+
+    #[test]
+    fn test_wildcard_fingerprint_keeps_different_ips() {
+        let fingerprint =
+            WildcardFingerprint::from_records(&[a_record("x1.example.com", "10.0.0.1", 60)]);
+        assert!(!fingerprint.matches(&[
+            a_record("www.example.com", "10.0.0.1", 60),
+            a_record("www.example.com", "10.0.0.9", 60),
+        ]));
+    }
+
+    #[test]
+    fn test_wildcard_fingerprint_matches_cname_target() {
+        let fingerprint = WildcardFingerprint::from_records(&[
+            cname_record("x1.example.com", "parking.example.net"),
+            a_record("parking.example.net", "10.0.0.1", 60),
+        ]);
+        assert!(fingerprint.matches(&[
+            cname_record("www.example.com", "parking.example.net"),
+            a_record("parking.example.net", "10.0.0.7", 60),
+        ]));
+    }
+
+    #[test]
+    fn test_wildcard_fingerprint_empty_hit_does_not_match() {
+        let fingerprint =
+            WildcardFingerprint::from_records(&[a_record("x1.example.com", "10.0.0.1", 60)]);
+        assert!(!fingerprint.matches(&[]));
+    }
+
+    #[test]
+    fn test_wildcard_fingerprint_display() {
+        let fingerprint =
+            WildcardFingerprint::from_records(&[a_record("x1.example.com", "10.0.0.1", 60)]);
+        assert_eq!(fingerprint.to_string(), "ips=[10.0.0.1] cname=[] ttl=60");
+    }
+
+    #[test]
+    fn test_random_label_is_single_label() {
+        assert_eq!(random_label().unwrap().num_labels(), 1);
+    }
+
+    #[test]
+    fn test_to_fqdn_matches_queried_name() {
+        let parent = Name::from_str("dev.example.com").unwrap();
+        let hit = Name::from_str("api.dev.example.com.").unwrap();
+        assert_eq!(to_fqdn(&parent), to_fqdn(&hit.base_name()));
+    }
+}
@@ -0,0 +1,413 @@
+use crate::pattern::Pattern;
+use crate::progress;
+use colored::*;
+use flate2::bufread::MultiGzDecoder;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, prelude::*, BufReader};
+
+/// The number of invalid entries reported individually before only a
+/// total is given.
+const REPORTED_SKIPS: usize = 10;
+
+/// Plain files up to this size are counted ahead of a run for its
+/// progress, larger and compressed files are not read twice.
+const COUNTED_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The number of words remembered to drop duplicates, eight bytes each.
+const SEEN_SLOTS: usize = 1 << 20;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Where the brute force candidates come from.
+pub enum Wordlist {
+    /// Subdomain files, one candidate per line, read in order.
+    Sources(Vec<Source>),
+    /// A pattern expanded lazily into candidates.
+    Pattern(Pattern),
+}
+
+/// A single subdomains file.
+pub enum Source {
+    /// A plain, gzip or zstd compressed file.
+    File(String),
+    /// Standard input, read as it is consumed.
+    Stdin,
+    /// Standard input read into memory so it can be used more than once.
+    Lines(Vec<String>),
+}
+
+impl Wordlist {
+    /// Build a wordlist from file paths, `-` standing for standard input.
+    ///
+    /// Standard input can only be read once, so when the wordlist has to
+    /// be `reusable` it is read into memory up front.
+    pub fn from_paths<'a>(
+        paths: impl IntoIterator<Item = &'a str>,
+        reusable: bool,
+    ) -> io::Result<Wordlist> {
+        let mut sources = vec![];
+        for path in paths {
+            let source = match path {
+                "-" if reusable => {
+                    let reader = decompress(BufReader::new(io::stdin()))?;
+                    Source::Lines(reader.lines().collect::<io::Result<_>>()?)
+                }
+                "-" => Source::Stdin,
+                path => Source::File(path.to_string()),
+            };
+            sources.push(source);
+        }
+        Ok(Wordlist::Sources(sources))
+    }
+
+    /// The candidate prefixes, read or generated as they are consumed.
+    pub fn words(&self) -> Box<dyn Iterator<Item = String> + Send + '_> {
+        match self {
+            Wordlist::Sources(sources) => Box::new(Words::new(sources)),
+            Wordlist::Pattern(pattern) => Box::new(pattern.iter()),
+        }
+    }
+
+    /// The number of candidates, when it is known before reading them.
+    pub fn keyspace(&self) -> Option<u128> {
+        match self {
+            Wordlist::Sources(_) => None,
+            Wordlist::Pattern(pattern) => Some(pattern.keyspace()),
+        }
+    }
+
+    /// An upper bound on the number of candidates, counting the lines of
+    /// the sources.  Unknown when a source can not be counted cheaply.
+    pub fn size_hint(&self) -> Option<u128> {
+        match self {
+            Wordlist::Sources(sources) => sources.iter().map(Source::lines).sum(),
+            Wordlist::Pattern(pattern) => Some(pattern.keyspace()),
+        }
+    }
+}
+
+impl Source {
+    /// The number of lines of standard input read into memory, or of a
+    /// plain file of at most [`COUNTED_BYTES`].
+    fn lines(&self) -> Option<u128> {
+        match self {
+            Source::File(path) => {
+                let file = File::open(path).ok()?;
+                if file.metadata().ok()?.len() > COUNTED_BYTES {
+                    return None;
+                }
+                let mut reader = BufReader::new(file);
+                let magic = reader.fill_buf().ok()?;
+                if magic.starts_with(&GZIP_MAGIC) || magic.starts_with(&ZSTD_MAGIC) {
+                    return None;
+                }
+                Some(reader.split(b'\n').count() as u128)
+            }
+            Source::Stdin => None,
+            Source::Lines(lines) => Some(lines.len() as u128),
+        }
+    }
+}
+
+/// Wrap `reader` in a decoder when it starts with a gzip or zstd header.
+fn decompress<R: BufRead + Send + 'static>(mut reader: R) -> io::Result<Box<dyn BufRead + Send>> {
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(BufReader::new(zstd::Decoder::with_buffer(
+            reader,
+        )?)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// The raw lines of a source.
+type Lines<'a> = Box<dyn Iterator<Item = io::Result<Vec<u8>>> + Send + 'a>;
+
+fn open_source(source: &Source) -> io::Result<Lines<'_>> {
+    match source {
+        Source::File(path) => {
+            let reader = decompress(BufReader::new(File::open(path)?))?;
+            Ok(Box::new(reader.split(b'\n')))
+        }
+        Source::Stdin => {
+            let reader = decompress(BufReader::new(io::stdin()))?;
+            Ok(Box::new(reader.split(b'\n')))
+        }
+        Source::Lines(lines) => Ok(Box::new(lines.iter().map(|l| Ok(l.clone().into_bytes())))),
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path),
+            Source::Stdin | Source::Lines(_) => write!(f, "<stdin>"),
+        }
+    }
+}
+
+/// Normalise a wordlist line into a candidate prefix.
+///
+/// Returns `Ok(None)` for blank lines and comments, and the reason the
+/// line was rejected for entries that can not be a subdomain.
+pub fn normalise(line: &str) -> Result<Option<String>, String> {
+    let word = line.split('#').next().unwrap_or("").trim();
+    let word = word.trim_end_matches('.').to_lowercase();
+    if word.is_empty() {
+        return Ok(None);
+    }
+    if word.len() > 253 {
+        return Err("name is longer than 253 characters".to_string());
+    }
+    for label in word.split('.') {
+        if label.is_empty() {
+            return Err("empty label".to_string());
+        }
+        if label.len() > 63 {
+            return Err(format!("label {} is longer than 63 characters", label));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(format!("invalid character {:?}", c));
+        }
+    }
+    Ok(Some(word))
+}
+
+/// A 64 bit hash standing in for a value in the tables used to drop
+/// duplicates.
+pub fn fingerprint<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The values seen lately, in a fixed amount of memory however long the
+/// wordlist.
+///
+/// Each value has one slot, and is forgotten once another value takes
+/// it, so a duplicate far from the first occurrence may get through.
+struct Seen {
+    slots: Vec<u64>,
+}
+
+impl Seen {
+    fn new() -> Seen {
+        Seen {
+            slots: vec![0; SEEN_SLOTS],
+        }
+    }
+
+    /// Remember `value`, false when it was seen already.
+    fn insert<T: Hash + ?Sized>(&mut self, value: &T) -> bool {
+        let fingerprint = fingerprint(value);
+        let slot = &mut self.slots[(fingerprint % SEEN_SLOTS as u64) as usize];
+        if *slot == fingerprint {
+            return false;
+        }
+        *slot = fingerprint;
+        true
+    }
+}
+
+/// The normalised words of the sources, without duplicates.
+struct Words<'a> {
+    sources: std::slice::Iter<'a, Source>,
+    current: Option<(&'a Source, Lines<'a>)>,
+    line: usize,
+    seen: Seen,
+    skipped: usize,
+}
+
+impl<'a> Words<'a> {
+    fn new(sources: &'a [Source]) -> Words<'a> {
+        Words {
+            sources: sources.iter(),
+            current: None,
+            line: 0,
+            seen: Seen::new(),
+            skipped: 0,
+        }
+    }
+
+    fn skip(&mut self, source: &Source, entry: &str, reason: &str) {
+        self.skipped += 1;
+        if self.skipped <= REPORTED_SKIPS {
+            progress::note(format!(
+                "[{}] Skipping {:?} at {}:{}: {}",
+                "WRN".yellow(),
+                entry,
+                source,
+                self.line,
+                reason
+            ));
+        }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            let (source, lines) = match &mut self.current {
+                Some((source, lines)) => (*source, lines),
+                None => {
+                    let source = match self.sources.next() {
+                        Some(source) => source,
+                        None => {
+                            if self.skipped > REPORTED_SKIPS {
+                                progress::note(format!(
+                                    "[{}] Skipped {} invalid wordlist entries",
+                                    "WRN".yellow(),
+                                    self.skipped
+                                ));
+                            }
+                            self.skipped = 0;
+                            return None;
+                        }
+                    };
+                    match open_source(source) {
+                        Ok(lines) => {
+                            self.line = 0;
+                            self.current = Some((source, lines));
+                        }
+                        Err(e) => progress::note(format!(
+                            "[{}] Could not read {}: {}",
+                            "ERR".red(),
+                            source,
+                            e
+                        )),
+                    }
+                    continue;
+                }
+            };
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => {
+                    progress::note(format!(
+                        "[{}] Could not read {}: {}",
+                        "ERR".red(),
+                        source,
+                        e
+                    ));
+                    self.current = None;
+                    continue;
+                }
+                None => {
+                    self.current = None;
+                    continue;
+                }
+            };
+            self.line += 1;
+            let line = String::from_utf8_lossy(&line);
+            match normalise(&line) {
+                Ok(Some(word)) => {
+                    if self.seen.insert(&word) {
+                        return Some(word);
+                    }
+                }
+                Ok(None) => (),
+                Err(reason) => self.skip(source, line.trim(), &reason),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn lines(lines: &[&str]) -> Source {
+        Source::Lines(lines.iter().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(normalise("  WWW \r"), Ok(Some("www".to_string())));
+        assert_eq!(normalise("api.dev."), Ok(Some("api.dev".to_string())));
+        assert_eq!(
+            normalise("mail # mail servers"),
+            Ok(Some("mail".to_string()))
+        );
+        assert_eq!(normalise("_dmarc"), Ok(Some("_dmarc".to_string())));
+        assert_eq!(normalise(""), Ok(None));
+        assert_eq!(normalise("# comment"), Ok(None));
+        assert!(normalise("two words").is_err());
+        assert!(normalise("a..b").is_err());
+        assert!(normalise(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_words_dedupe_across_sources() {
+        let wordlist = Wordlist::Sources(vec![
+            lines(&["www", "mail", "", "# comment"]),
+            lines(&["MAIL", "bad word", "ftp"]),
+        ]);
+        assert_eq!(
+            wordlist.words().collect::<Vec<_>>(),
+            vec!["www", "mail", "ftp"]
+        );
+    }
+
+    #[test]
+    fn test_size_hint() {
+        let wordlist = Wordlist::Sources(vec![lines(&["www", "mail"]), lines(&["ftp"])]);
+        assert_eq!(wordlist.size_hint(), Some(3));
+        assert_eq!(Wordlist::Sources(vec![Source::Stdin]).size_hint(), None);
+        // Compressed files are not decompressed ahead of the run.
+        let path = std::env::temp_dir().join(format!("size-hint-{}.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(b"www\nmail\n").unwrap();
+        encoder.finish().unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let compressed = Wordlist::Sources(vec![lines(&["ftp"]), Source::File(path.clone())]);
+        assert_eq!(compressed.size_hint(), None);
+        assert_eq!(compressed.words().count(), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_seen() {
+        let mut seen = Seen::new();
+        assert!(seen.insert("www"));
+        assert!(seen.insert("mail"));
+        assert!(!seen.insert("www"));
+        assert_eq!(seen.slots.len(), SEEN_SLOTS);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"www\nmail\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let reader = decompress(io::Cursor::new(compressed)).unwrap();
+        let words = reader.lines().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(words, vec!["www", "mail"]);
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let compressed = zstd::encode_all(&b"www\nmail\n"[..], 0).unwrap();
+        let reader = decompress(io::Cursor::new(compressed)).unwrap();
+        let words = reader.lines().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(words, vec!["www", "mail"]);
+    }
+
+    #[test]
+    fn test_decompress_plain() {
+        let reader = decompress(io::Cursor::new(b"www\n".to_vec())).unwrap();
+        let words = reader.lines().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(words, vec!["www"]);
+    }
+}
@@ -0,0 +1,543 @@
+use trust_dns_proto::rr::dnssec::rdata::DNSSECRData;
+use trust_dns_proto::rr::dnssec::rdata::DNSSECRecordType;
+use trust_dns_proto::rr::rdata::SOA;
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::BinEncoder;
+
+/// The TTL of a made up SOA, and its timers.
+const MADE_UP_TTL: u32 = 3600;
+
+/// Write `records` as an RFC 1035 master file for the zone at `origin`.
+///
+/// Duplicates are dropped and the records sorted with the first SOA record
+/// of the zone leading, so the transfers from several name servers give
+/// one zone.  Without one, as for the results of brute force, a SOA is
+/// made up so the file loads.  Records outside the zone, such as the
+/// targets CNAME chains lead to, are kept as comments.  Types without a
+/// presentation format here are written in the RFC 3597 generic format.
+pub fn master_file(origin: &Name, records: &[Record]) -> String {
+    let is_soa =
+        |record: &Record| record.record_type() == RecordType::SOA && record.name() == origin;
+    let soa = records
+        .iter()
+        .find(|record| is_soa(record))
+        .cloned()
+        .unwrap_or_else(|| made_up_soa(origin, records));
+    // A zone has one SOA record, any others are from differing transfers.
+    let mut records = records
+        .iter()
+        .filter(|record| !is_soa(record))
+        .cloned()
+        .collect::<Vec<_>>();
+    records.sort();
+    records.dedup();
+    let (records, outside): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|record| origin.zone_of(record.name()));
+    let mut file = format!("$ORIGIN {}\n", name(origin));
+    for record in std::iter::once(&soa).chain(&records) {
+        file.push_str(&line(owner(origin, record.name()), record, ""));
+    }
+    for record in &outside {
+        file.push_str(&line(name(record.name()), record, "; out of zone: "));
+    }
+    file
+}
+
+/// The line of a record, after `prefix`.
+fn line(owner: String, record: &Record, prefix: &str) -> String {
+    match presentation(record) {
+        Ok((record_type, rdata)) => format!(
+            "{}{}\t{}\t{}\t{}\t{}\n",
+            prefix,
+            owner,
+            record.ttl(),
+            record.dns_class(),
+            record_type,
+            rdata
+        ),
+        Err(e) => format!("; {} left out: {}\n", owner, e),
+    }
+}
+
+/// A SOA for a zone whose own was not found, naming the first of its name
+/// servers among `records` as primary, with the serial at zero.
+fn made_up_soa(origin: &Name, records: &[Record]) -> Record {
+    let primary = records
+        .iter()
+        .filter(|record| record.name() == origin)
+        .find_map(|record| match record.rdata() {
+            RData::NS(ns) => Some(ns.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(|| origin.clone());
+    let hostmaster = Name::from_labels(vec!["hostmaster"])
+        .map(|hostmaster| hostmaster.append_domain(origin))
+        .unwrap_or_else(|_| origin.clone());
+    Record::from_rdata(
+        origin.clone(),
+        MADE_UP_TTL,
+        RData::SOA(SOA::new(
+            primary,
+            hostmaster,
+            0,
+            MADE_UP_TTL as i32,
+            MADE_UP_TTL as i32,
+            MADE_UP_TTL as i32 * 7,
+            MADE_UP_TTL,
+        )),
+    )
+}
+
+/// An owner name relative to the origin when under it.
+fn owner(origin: &Name, owner: &Name) -> String {
+    if owner == origin {
+        return "@".to_string();
+    }
+    if !origin.zone_of(owner) {
+        return name(owner);
+    }
+    let labels = owner.iter().count() - origin.iter().count();
+    owner
+        .iter()
+        .take(labels)
+        .map(label)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The type and RDATA of a record in presentation format.
+pub fn presentation(record: &Record) -> Result<(String, String), String> {
+    let rdata = match record.rdata() {
+        RData::A(ip) => ip.to_string(),
+        RData::AAAA(ip) => ip.to_string(),
+        RData::CNAME(target) | RData::NS(target) | RData::PTR(target) => name(target),
+        RData::MX(mx) => format!("{} {}", mx.preference(), name(mx.exchange())),
+        RData::NAPTR(naptr) => format!(
+            "{} {} {} {} {} {}",
+            naptr.order(),
+            naptr.preference(),
+            character_string(naptr.flags()),
+            character_string(naptr.services()),
+            character_string(naptr.regexp()),
+            name(naptr.replacement())
+        ),
+        RData::SOA(soa) => format!(
+            "{} {} {} {} {} {} {}",
+            name(soa.mname()),
+            name(soa.rname()),
+            soa.serial(),
+            soa.refresh(),
+            soa.retry(),
+            soa.expire(),
+            soa.minimum()
+        ),
+        RData::SRV(srv) => format!(
+            "{} {} {} {}",
+            srv.priority(),
+            srv.weight(),
+            srv.port(),
+            name(srv.target())
+        ),
+        RData::TXT(txt) => txt
+            .txt_data()
+            .iter()
+            .map(|data| character_string(data))
+            .collect::<Vec<_>>()
+            .join(" "),
+        RData::CAA(caa) => {
+            // The value is written as it is sent, after the flags and tag.
+            let bytes = wire(record.record_type(), record.rdata())?;
+            let value = bytes.get(2 + caa.tag().as_str().len()..).unwrap_or(&[]);
+            format!(
+                "{} {} {}",
+                if caa.issuer_critical() { 128 } else { 0 },
+                caa.tag().as_str(),
+                character_string(value)
+            )
+        }
+        RData::SSHFP(sshfp) => format!(
+            "{} {} {}",
+            u8::from(sshfp.algorithm()),
+            u8::from(sshfp.fingerprint_type()),
+            hex(sshfp.fingerprint())
+        ),
+        RData::TLSA(tlsa) => format!(
+            "{} {} {} {}",
+            u8::from(tlsa.cert_usage()),
+            u8::from(tlsa.selector()),
+            u8::from(tlsa.matching()),
+            hex(tlsa.cert_data())
+        ),
+        RData::OPENPGPKEY(key) => base64::encode(key.public_key()),
+        RData::DNSSEC(dnssec) => match dnssec_presentation(dnssec) {
+            Some(rdata) => rdata,
+            None => return generic(record.record_type(), record.rdata()),
+        },
+        rdata => return generic(record.record_type(), rdata),
+    };
+    Ok((type_name(record.record_type()), rdata))
+}
+
+/// The RDATA of the DNSSEC types in presentation format, as BIND writes
+/// them in signed zones.
+fn dnssec_presentation(dnssec: &DNSSECRData) -> Option<String> {
+    let rdata = match dnssec {
+        DNSSECRData::DNSKEY(key) => {
+            let flags = (key.zone_key() as u16) << 8
+                | (key.revoke() as u16) << 7
+                | key.secure_entry_point() as u16;
+            format!(
+                "{} 3 {} {}",
+                flags,
+                u8::from(key.algorithm()),
+                base64::encode(key.public_key())
+            )
+        }
+        DNSSECRData::DS(ds) => format!(
+            "{} {} {} {}",
+            ds.key_tag(),
+            u8::from(ds.algorithm()),
+            u8::from(ds.digest_type()),
+            hex(ds.digest())
+        ),
+        DNSSECRData::SIG(sig) => format!(
+            "{} {} {} {} {} {} {} {} {}",
+            type_name(sig.type_covered()),
+            u8::from(sig.algorithm()),
+            sig.num_labels(),
+            sig.original_ttl(),
+            timestamp(sig.sig_expiration()),
+            timestamp(sig.sig_inception()),
+            sig.key_tag(),
+            name(sig.signer_name()),
+            base64::encode(sig.sig())
+        ),
+        DNSSECRData::NSEC(nsec) => format!(
+            "{} {}",
+            name(nsec.next_domain_name()),
+            type_names(nsec.type_bit_maps())
+        ),
+        DNSSECRData::NSEC3(nsec3) => format!(
+            "{} {} {} {} {} {}",
+            u8::from(nsec3.hash_algorithm()),
+            nsec3.opt_out() as u8,
+            nsec3.iterations(),
+            salt(nsec3.salt()),
+            base32hex(nsec3.next_hashed_owner_name()),
+            type_names(nsec3.type_bit_maps())
+        ),
+        DNSSECRData::NSEC3PARAM(param) => format!(
+            "{} {} {} {}",
+            u8::from(param.hash_algorithm()),
+            param.opt_out() as u8,
+            param.iterations(),
+            salt(param.salt())
+        ),
+        _ => return None,
+    };
+    Some(rdata.trim_end().to_string())
+}
+
+/// The mnemonic of a type, or `TYPEnnn` for those without one here.
+pub fn type_name(record_type: RecordType) -> String {
+    match record_type {
+        RecordType::Unknown(code) | RecordType::DNSSEC(DNSSECRecordType::Unknown(code)) => {
+            format!("TYPE{}", code)
+        }
+        record_type => record_type.to_string(),
+    }
+}
+
+/// The types of an NSEC or NSEC3 bitmap.
+fn type_names(record_types: &[RecordType]) -> String {
+    let mut record_types = record_types.to_vec();
+    record_types.sort();
+    record_types
+        .into_iter()
+        .map(type_name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// An NSEC3 salt in hex, `-` when empty.
+fn salt(salt: &[u8]) -> String {
+    if salt.is_empty() {
+        "-".to_string()
+    } else {
+        hex(salt)
+    }
+}
+
+/// A signature time as `YYYYMMDDHHmmSS` in UTC.
+fn timestamp(seconds: u32) -> String {
+    let days = i64::from(seconds / 86400);
+    let time = seconds % 86400;
+    // Howard Hinnant's civil from days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The RFC 4648 base32 encoding with the extended hex alphabet, unpadded.
+fn base32hex(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = buffer << 8 | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// The RFC 3597 `TYPEnnn \# length hex` format.
+fn generic(record_type: RecordType, rdata: &RData) -> Result<(String, String), String> {
+    let bytes = wire(record_type, rdata)?;
+    Ok((
+        format!("TYPE{}", u16::from(record_type)),
+        format!("\\# {} {}", bytes.len(), hex(&bytes))
+            .trim_end()
+            .to_string(),
+    ))
+}
+
+/// The RDATA as it is sent.
+fn wire(record_type: RecordType, rdata: &RData) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut encoder = BinEncoder::new(&mut bytes);
+    // Names in the RDATA must not point outside it.
+    encoder.set_canonical_names(true);
+    rdata.emit(&mut encoder).map_err(|e| {
+        format!(
+            "TYPE{} could not be encoded: {}",
+            u16::from(record_type),
+            e.kind()
+        )
+    })?;
+    Ok(bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// A name in presentation format, absolute.
+pub fn name(name: &Name) -> String {
+    if name.is_root() {
+        return ".".to_string();
+    }
+    name.iter().map(label).collect::<Vec<_>>().join(".") + "."
+}
+
+/// A label with the characters special to master files escaped, and
+/// those that are not printable as `\DDD` in decimal.
+fn label(label: &[u8]) -> String {
+    label
+        .iter()
+        .map(|byte| match *byte {
+            b if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'*' => {
+                (b as char).to_string()
+            }
+            b if b.is_ascii_graphic() => format!("\\{}", b as char),
+            b => format!("\\{:03}", b),
+        })
+        .collect()
+}
+
+/// A character string quoted, with quotes and backslashes escaped and
+/// the bytes that are not printable as `\DDD` in decimal.
+pub fn character_string(data: &[u8]) -> String {
+    let escaped = data
+        .iter()
+        .map(|byte| match *byte {
+            b'"' => "\\\"".to_string(),
+            b'\\' => "\\\\".to_string(),
+            b if b == b' ' || b.is_ascii_graphic() => (b as char).to_string(),
+            b => format!("\\{:03}", b),
+        })
+        .collect::<String>();
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use trust_dns_proto::rr::rdata::{NULL, TXT};
+
+    fn domain(name: &str) -> Name {
+        Name::from_str(name).unwrap()
+    }
+
+    fn soa(serial: u32) -> Record {
+        Record::from_rdata(
+            domain("example.com."),
+            3600,
+            RData::SOA(SOA::new(
+                domain("ns1.example.com."),
+                domain("hostmaster.example.com."),
+                serial,
+                3600,
+                600,
+                86400,
+                60,
+            )),
+        )
+    }
+
+    #[test]
+    fn test_master_file() {
+        let www = Record::from_rdata(
+            domain("www.example.com."),
+            300,
+            RData::A("10.0.0.1".parse().unwrap()),
+        );
+        let txt = Record::from_rdata(
+            domain("example.com."),
+            300,
+            RData::TXT(TXT::new(vec![
+                "v=spf1 -all".to_string(),
+                "say \"hi\"\\\u{7}".to_string(),
+            ])),
+        );
+        let unknown = Record::from_rdata(
+            domain("x.example.com."),
+            60,
+            RData::Unknown {
+                code: 65280,
+                rdata: NULL::with(vec![0xde, 0xad]),
+            },
+        );
+        let outside = Record::from_rdata(
+            domain("www.example.net."),
+            60,
+            RData::CNAME(domain("www.example.com.")),
+        );
+        let records = vec![
+            soa(7),
+            www.clone(),
+            txt,
+            unknown,
+            outside,
+            www,
+            soa(7),
+            soa(6),
+        ];
+        assert_eq!(
+            master_file(&domain("example.com."), &records),
+            "$ORIGIN example.com.\n\
+             @\t3600\tIN\tSOA\tns1.example.com. hostmaster.example.com. 7 3600 600 86400 60\n\
+             @\t300\tIN\tTXT\t\"v=spf1 -all\" \"say \\\"hi\\\"\\\\\\007\"\n\
+             www\t300\tIN\tA\t10.0.0.1\n\
+             x\t60\tIN\tTYPE65280\t\\# 2 DEAD\n\
+             ; out of zone: www.example.net.\t60\tIN\tCNAME\twww.example.com.\n"
+        );
+    }
+
+    #[test]
+    fn test_master_file_without_soa() {
+        let ns = Record::from_rdata(
+            domain("example.com."),
+            300,
+            RData::NS(domain("ns1.example.com.")),
+        );
+        let www = Record::from_rdata(
+            domain("www.example.com."),
+            300,
+            RData::CNAME(domain("www.example.net.")),
+        );
+        let target = Record::from_rdata(
+            domain("www.example.net."),
+            60,
+            RData::A("10.0.0.1".parse().unwrap()),
+        );
+        assert_eq!(
+            master_file(&domain("example.com."), &[www, target, ns]),
+            "$ORIGIN example.com.\n\
+             @\t3600\tIN\tSOA\tns1.example.com. hostmaster.example.com. 0 3600 3600 25200 3600\n\
+             @\t300\tIN\tNS\tns1.example.com.\n\
+             www\t300\tIN\tCNAME\twww.example.net.\n\
+             ; out of zone: www.example.net.\t60\tIN\tA\t10.0.0.1\n"
+        );
+    }
+
+    #[test]
+    fn test_presentation_mnemonics() {
+        use trust_dns_proto::rr::dnssec::rdata::{DNSKEY, DS, NSEC};
+        use trust_dns_proto::rr::dnssec::{Algorithm, DigestType};
+        use trust_dns_proto::rr::rdata::CAA;
+        let presented = |rdata: RData| {
+            let (record_type, rdata) =
+                presentation(&Record::from_rdata(domain("example.com."), 60, rdata)).unwrap();
+            format!("{} {}", record_type, rdata)
+        };
+        assert_eq!(
+            presented(RData::DNSSEC(DNSSECRData::DNSKEY(DNSKEY::new(
+                true,
+                true,
+                false,
+                Algorithm::ECDSAP256SHA256,
+                vec![1, 2, 3],
+            )))),
+            "DNSKEY 257 3 13 AQID"
+        );
+        assert_eq!(
+            presented(RData::DNSSEC(DNSSECRData::DS(DS::new(
+                2371,
+                Algorithm::ECDSAP256SHA256,
+                DigestType::SHA256,
+                vec![0xab, 0xcd],
+            )))),
+            "DS 2371 13 2 ABCD"
+        );
+        assert_eq!(
+            presented(RData::DNSSEC(DNSSECRData::NSEC(NSEC::new(
+                domain("www.example.com."),
+                vec![RecordType::MX, RecordType::A],
+            )))),
+            "NSEC www.example.com. A MX"
+        );
+        assert_eq!(
+            presented(RData::CAA(CAA::new_issue(
+                false,
+                Some(domain("letsencrypt.org")),
+                vec![],
+            ))),
+            "CAA 0 issue \"letsencrypt.org\""
+        );
+        assert_eq!(timestamp(1_600_000_000), "20200913122640");
+        assert_eq!(base32hex(b"foobar"), "CPNMUOJ1E8");
+    }
+
+    #[test]
+    fn test_label_escapes() {
+        assert_eq!(label(b"a.b"), "a\\.b");
+        assert_eq!(label(b"*"), "*");
+        assert_eq!(label(b"tab\there"), "tab\\009here");
+        assert_eq!(character_string(b"a;b c"), "\"a;b c\"");
+    }
+}